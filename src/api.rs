@@ -1,3 +1,6 @@
+use crate::logs::LogStore;
+use crate::store::NamespacedName;
+use axum::extract::{Path, State};
 use axum::http::header;
 use axum::http::HeaderMap;
 use axum::http::HeaderValue;
@@ -5,13 +8,158 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::routing::get;
-use axum::Router;
+use axum::{Json, Router};
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Reconciles running longer than this are considered stuck, well above the default helmfile timeout
+const RECONCILE_STALL_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// Watches failing for longer than this are considered broken
+const WATCH_FAILURE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// A controller loop without a heartbeat for longer than this is considered stalled
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Problems that make the controller unready, by the name of the check that found them
+#[derive(Clone, Default)]
+pub struct Readiness {
+    problems: Arc<RwLock<BTreeMap<String, String>>>,
+}
+
+impl Readiness {
+    pub fn set(&self, check: &str, problem: Option<String>) {
+        let mut problems = self.problems.write().unwrap();
+        match problem {
+            Some(problem) => problems.insert(check.to_owned(), problem),
+            None => problems.remove(check),
+        };
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let problems = self.problems.read().unwrap();
+        problems
+            .iter()
+            .map(|(check, problem)| format!("{check}: {problem}"))
+            .collect()
+    }
+}
+
+/// Watchdog for the controller loop, tracks its heartbeat, running reconciles and failing watches
+#[derive(Clone, Default)]
+pub struct Liveness {
+    state: Arc<Mutex<LivenessState>>,
+}
+
+#[derive(Default)]
+struct LivenessState {
+    reconciles: HashMap<NamespacedName, Instant>,
+    failing_watches: HashMap<&'static str, Instant>,
+    /// last heartbeat of the controller loop, none before it started (e.g. on standbys)
+    heartbeat: Option<Instant>,
+    exited: bool,
+}
+
+impl Liveness {
+    pub fn heartbeat(&self) {
+        self.state.lock().unwrap().heartbeat = Some(Instant::now());
+    }
+
+    /// Marks the controller loop as exited without a shutdown
+    pub fn loop_exited(&self) {
+        self.state.lock().unwrap().exited = true;
+    }
+
+    pub fn reconcile_started(&self, key: NamespacedName) {
+        self.state
+            .lock()
+            .unwrap()
+            .reconciles
+            .insert(key, Instant::now());
+    }
+
+    pub fn reconcile_finished(&self, key: &NamespacedName) {
+        self.state.lock().unwrap().reconciles.remove(key);
+    }
+
+    pub fn watch_succeeded(&self, kind: &'static str) {
+        self.state.lock().unwrap().failing_watches.remove(kind);
+    }
+
+    pub fn watch_failed(&self, kind: &'static str) {
+        self.state
+            .lock()
+            .unwrap()
+            .failing_watches
+            .entry(kind)
+            .or_insert_with(Instant::now);
+    }
+
+    fn problems(&self, now: Instant) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let stalled = match state.heartbeat {
+            _ if state.exited => Some("controller loop has exited".to_owned()),
+            Some(heartbeat) if now.duration_since(heartbeat) > HEARTBEAT_TIMEOUT => Some(format!(
+                "controller loop has no heartbeat since {}s",
+                now.duration_since(heartbeat).as_secs()
+            )),
+            _ => None,
+        };
+        let stuck = state
+            .reconciles
+            .iter()
+            .filter(|(_, started)| now.duration_since(**started) > RECONCILE_STALL_TIMEOUT)
+            .map(|(key, started)| {
+                format!(
+                    "reconcile of {}/{} is running since {}s",
+                    key.namespace,
+                    key.name,
+                    now.duration_since(*started).as_secs()
+                )
+            });
+        let failing = state
+            .failing_watches
+            .iter()
+            .filter(|(_, since)| now.duration_since(**since) > WATCH_FAILURE_TIMEOUT)
+            .map(|(kind, since)| {
+                format!(
+                    "watch of {kind} is failing since {}s",
+                    now.duration_since(*since).as_secs()
+                )
+            });
+        let mut problems: Vec<_> = stuck.chain(failing).chain(stalled).collect();
+        problems.sort();
+        problems
+    }
+}
+
+#[derive(Clone)]
+struct ApiState {
+    logs: LogStore,
+    readiness: Readiness,
+    liveness: Liveness,
+}
 
 async fn health() -> &'static str {
     "OK"
 }
 
+async fn ready(State(state): State<ApiState>) -> Response {
+    probe_response(state.readiness.problems())
+}
+
+async fn live(State(state): State<ApiState>) -> Response {
+    probe_response(state.liveness.problems(Instant::now()))
+}
+
+fn probe_response(problems: Vec<String>) -> Response {
+    if problems.is_empty() {
+        "OK".into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, problems.join("\n")).into_response()
+    }
+}
+
 async fn metrics() -> Response {
     if let Ok(body) = crate::metrics::metrics().await {
         let mut headers = HeaderMap::new();
@@ -29,12 +177,35 @@ async fn metrics() -> Response {
     }
 }
 
-pub async fn server() {
+/// Returns the output of the last helmfile and helm commands run for a Helmfile
+async fn logs(
+    State(state): State<ApiState>,
+    Path((namespace, name)): Path<(String, String)>,
+) -> Response {
+    match state.logs.get(&NamespacedName { name, namespace }).await {
+        Some(entries) => Json(entries).into_response(),
+        None => (StatusCode::NOT_FOUND, "no logs found").into_response(),
+    }
+}
+
+pub async fn server(
+    addr: SocketAddr,
+    logs_store: LogStore,
+    readiness: Readiness,
+    liveness: Liveness,
+) {
     let app = Router::new()
         .route("/health", get(health))
-        .route("/metrics", get(metrics));
+        .route("/readyz", get(ready))
+        .route("/livez", get(live))
+        .route("/metrics", get(metrics))
+        .route("/logs/:namespace/:name", get(logs))
+        .with_state(ApiState {
+            logs: logs_store,
+            readiness,
+            liveness,
+        });
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
     tracing::info!("Listening on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -43,3 +214,72 @@ pub async fn server() {
         tracing::error!("Encountered error serving api: {e}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liveness() {
+        let liveness = Liveness::default();
+        let key = NamespacedName {
+            name: "foo".to_owned(),
+            namespace: "bar".to_owned(),
+        };
+        let now = Instant::now();
+        liveness.reconcile_started(key.clone());
+        liveness.watch_failed("helmfiles");
+        assert!(liveness.problems(now).is_empty());
+
+        let later = now + RECONCILE_STALL_TIMEOUT + Duration::from_secs(1);
+        let problems = liveness.problems(later);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("reconcile of bar/foo is running since"));
+        assert!(problems[1].starts_with("watch of helmfiles is failing since"));
+
+        liveness.reconcile_finished(&key);
+        liveness.watch_succeeded("helmfiles");
+        assert!(liveness.problems(later).is_empty());
+    }
+
+    #[test]
+    fn test_liveness_heartbeat() {
+        let liveness = Liveness::default();
+        let now = Instant::now();
+        let later = now + HEARTBEAT_TIMEOUT + Duration::from_secs(1);
+        // not started yet
+        assert!(liveness.problems(later).is_empty());
+
+        liveness.heartbeat();
+        assert!(liveness.problems(now).is_empty());
+        let problems = liveness.problems(later);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("controller loop has no heartbeat since"));
+
+        liveness.heartbeat();
+        liveness.loop_exited();
+        assert_eq!(
+            liveness.problems(Instant::now()),
+            vec!["controller loop has exited"]
+        );
+    }
+
+    #[test]
+    fn test_readiness() {
+        let readiness = Readiness::default();
+        readiness.set("helmfiles", Some("initial list not yet synced".to_owned()));
+        readiness.set(
+            "preflight",
+            Some("required tools not found: helm".to_owned()),
+        );
+        assert_eq!(
+            readiness.problems(),
+            vec![
+                "helmfiles: initial list not yet synced",
+                "preflight: required tools not found: helm"
+            ]
+        );
+        readiness.set("helmfiles", None);
+        assert_eq!(readiness.problems().len(), 1);
+    }
+}
@@ -0,0 +1,647 @@
+use crate::error::{Error, Result};
+use argh::FromArgs;
+use regex::Regex;
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_CONCURRENCY: u16 = 2;
+const DEFAULT_BIND_ADDRESS: ([u8; 4], u16) = ([0, 0, 0, 0], 8080);
+const DEFAULT_REQUEUE_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_REQUEUE_ERROR_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_REQUEUE_PENDING_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Flux helmfile controller
+#[derive(FromArgs, Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct Config {
+    /// path to a yaml file with settings, keys are the flag names in camelCase, flags and environment variables take precedence (env: CONFIG_FILE)
+    #[argh(option)]
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+    /// number of Helmfiles reconciled in parallel, default is 2 (env: CONCURRENCY)
+    #[argh(option)]
+    pub concurrency: Option<u16>,
+    /// address to serve metrics, probes and logs on, default is 0.0.0.0:8080 (env: BIND_ADDRESS)
+    #[argh(option)]
+    pub bind_address: Option<SocketAddr>,
+    /// interval to reconcile Helmfiles without spec.interval, default is 5m (env: REQUEUE_INTERVAL)
+    #[argh(option, from_str_fn(parse_duration_arg))]
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub requeue_interval: Option<Duration>,
+    /// interval to retry failed reconciles, default is 30s (env: REQUEUE_ERROR_INTERVAL)
+    #[argh(option, from_str_fn(parse_duration_arg))]
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub requeue_error_interval: Option<Duration>,
+    /// interval to check again for missing sources and pending health checks, default is 10s (env: REQUEUE_PENDING_INTERVAL)
+    #[argh(option, from_str_fn(parse_duration_arg))]
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub requeue_pending_interval: Option<Duration>,
+    /// timeout for helmfile and helm commands of Helmfiles without spec.options.timeout, default is 10m (env: DEFAULT_TIMEOUT)
+    #[argh(option, from_str_fn(parse_duration_arg))]
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub default_timeout: Option<Duration>,
+    /// time to wait for running helmfile commands on shutdown before they are terminated, default is 5m (env: SHUTDOWN_TIMEOUT)
+    #[argh(option, from_str_fn(parse_duration_arg))]
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub shutdown_timeout: Option<Duration>,
+    /// existing directory for extracted artifacts and decryption keys, default is the system temp dir (env: TEMP_DIR)
+    #[argh(option)]
+    pub temp_dir: Option<PathBuf>,
+    /// existing directory for the helm and helmfile caches of local runs (env: CACHE_DIR)
+    #[argh(option)]
+    pub cache_dir: Option<PathBuf>,
+    /// log format: plain (default) or json (env: LOG_FORMAT, or LOGGING_MODE)
+    #[argh(option)]
+    #[serde(deserialize_with = "deserialize_parsed")]
+    pub log_format: Option<LogFormat>,
+    /// namespace to watch, can be repeated, default is all namespaces (env: WATCH_NAMESPACES, comma separated)
+    #[argh(option)]
+    pub namespace: Vec<String>,
+    /// label selector for the namespaces to watch, e.g. tenant=team-a, not together with namespace (env: WATCH_NAMESPACE_SELECTOR)
+    #[argh(option)]
+    pub namespace_selector: Option<String>,
+    /// base url of the flux source-controller, artifact urls are rewritten to it (env: SOURCE_CONTROLLER_URL)
+    #[argh(option)]
+    pub source_controller_url: Option<Url>,
+    /// path to a PEM file with CA certificates to trust for the source-controller (env: SOURCE_CONTROLLER_CA_FILE)
+    #[argh(option)]
+    pub source_controller_ca_file: Option<PathBuf>,
+    /// path to a PEM client certificate for mTLS with the source-controller (env: SOURCE_CONTROLLER_CERT_FILE)
+    #[argh(option)]
+    pub source_controller_cert_file: Option<PathBuf>,
+    /// path to the PEM private key for the client certificate (env: SOURCE_CONTROLLER_KEY_FILE)
+    #[argh(option)]
+    pub source_controller_key_file: Option<PathBuf>,
+    /// url of an HTTP proxy to use for fetching artifacts (env: SOURCE_CONTROLLER_PROXY)
+    #[argh(option)]
+    pub source_controller_proxy: Option<Url>,
+    /// address of the flux notification-controller to forward events to, e.g. http://notification-controller.flux-system.svc.cluster.local./ (env: EVENTS_ADDR)
+    #[argh(option)]
+    pub events_addr: Option<Url>,
+    /// path to a yaml file with webhooks to notify about reconcile results (env: WEBHOOK_CONFIG)
+    #[argh(option)]
+    pub webhook_config: Option<PathBuf>,
+    /// only render and diff helmfiles for all objects, never apply or destroy (env: DRY_RUN=true)
+    #[argh(switch)]
+    pub dry_run: bool,
+    /// where to run helmfile: local (child processes of the controller, default) or job (env: EXECUTION_BACKEND)
+    #[argh(option)]
+    #[serde(deserialize_with = "deserialize_parsed")]
+    pub execution_backend: Option<ExecutionBackend>,
+    /// namespace to create helmfile jobs in, default is flux-system (env: JOB_NAMESPACE)
+    #[argh(option)]
+    pub job_namespace: Option<String>,
+    /// image with helmfile, helm and sops for helmfile jobs, required for the job backend (env: JOB_IMAGE)
+    #[argh(option)]
+    pub job_image: Option<String>,
+    /// serviceaccount for helmfile jobs, default is helmfile-controller (env: JOB_SERVICE_ACCOUNT)
+    #[argh(option)]
+    pub job_service_account: Option<String>,
+    /// name of a secret with a key kubeconfig to mount into helmfile jobs (env: JOB_KUBECONFIG_SECRET)
+    #[argh(option)]
+    pub job_kubeconfig_secret: Option<String>,
+    /// number of helmfile and helm command outputs to keep per Helmfile for the logs endpoint, default is 20 (env: LOG_HISTORY)
+    #[argh(option)]
+    pub log_history: Option<usize>,
+    /// label selector for the Helmfiles and GitRepositories of this instance, e.g. sharding.fluxcd.io/key=shard1 or tenant=team-a (env: WATCH_LABEL_SELECTOR)
+    #[argh(option)]
+    pub watch_label_selector: Option<String>,
+    /// elect a leader with a Lease so several replicas can run, only the leader reconciles (env: LEADER_ELECTION=true)
+    #[argh(switch)]
+    pub leader_election: bool,
+    /// namespace of the leader election Lease, default is flux-system (env: LEADER_ELECTION_NAMESPACE)
+    #[argh(option)]
+    pub leader_election_namespace: Option<String>,
+    /// name of the leader election Lease, default is helmfile-controller-leader, must differ between shards (env: LEADER_ELECTION_ID)
+    #[argh(option)]
+    pub leader_election_id: Option<String>,
+    /// regex for values to mask in helmfile output, status and logs, can be repeated (env: REDACT_PATTERNS, one per line)
+    #[argh(option)]
+    #[serde(deserialize_with = "deserialize_patterns")]
+    pub redact_pattern: Vec<Regex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExecutionBackend {
+    #[default]
+    Local,
+    Job,
+}
+
+impl std::str::FromStr for ExecutionBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "local" => Ok(ExecutionBackend::Local),
+            "job" => Ok(ExecutionBackend::Job),
+            _ => Err(format!(
+                "unknown execution backend '{value}', must be local or job"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format '{value}', must be plain or json"
+            )),
+        }
+    }
+}
+
+impl Config {
+    /// Parses the command line, fills unset options from environment variables and the config file
+    /// and validates the result
+    pub fn load() -> Result<Self> {
+        let config: Config = argh::from_env();
+        config.resolve(|key| std::env::var(key).ok())
+    }
+
+    fn resolve(self, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config = self.with_env(env)?;
+        if let Some(path) = config.config_file.clone() {
+            config = config.with_file(read_file(&path)?);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn concurrency(&self) -> u16 {
+        self.concurrency.unwrap_or(DEFAULT_CONCURRENCY)
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        self.bind_address
+            .unwrap_or_else(|| SocketAddr::from(DEFAULT_BIND_ADDRESS))
+    }
+
+    pub fn requeue_interval(&self) -> Duration {
+        self.requeue_interval.unwrap_or(DEFAULT_REQUEUE_INTERVAL)
+    }
+
+    pub fn requeue_error_interval(&self) -> Duration {
+        self.requeue_error_interval
+            .unwrap_or(DEFAULT_REQUEUE_ERROR_INTERVAL)
+    }
+
+    pub fn requeue_pending_interval(&self) -> Duration {
+        self.requeue_pending_interval
+            .unwrap_or(DEFAULT_REQUEUE_PENDING_INTERVAL)
+    }
+
+    pub fn default_timeout(&self) -> Duration {
+        self.default_timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    pub fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+
+    fn with_env(mut self, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        if self.config_file.is_none() {
+            self.config_file = env("CONFIG_FILE").map(PathBuf::from);
+        }
+        if self.concurrency.is_none() {
+            self.concurrency = parse_env(&env, "CONCURRENCY")?;
+        }
+        if self.bind_address.is_none() {
+            self.bind_address = parse_env(&env, "BIND_ADDRESS")?;
+        }
+        if self.requeue_interval.is_none() {
+            self.requeue_interval = duration_env(&env, "REQUEUE_INTERVAL")?;
+        }
+        if self.requeue_error_interval.is_none() {
+            self.requeue_error_interval = duration_env(&env, "REQUEUE_ERROR_INTERVAL")?;
+        }
+        if self.requeue_pending_interval.is_none() {
+            self.requeue_pending_interval = duration_env(&env, "REQUEUE_PENDING_INTERVAL")?;
+        }
+        if self.default_timeout.is_none() {
+            self.default_timeout = duration_env(&env, "DEFAULT_TIMEOUT")?;
+        }
+        if self.shutdown_timeout.is_none() {
+            self.shutdown_timeout = duration_env(&env, "SHUTDOWN_TIMEOUT")?;
+        }
+        if self.temp_dir.is_none() {
+            self.temp_dir = env("TEMP_DIR").map(PathBuf::from);
+        }
+        if self.cache_dir.is_none() {
+            self.cache_dir = env("CACHE_DIR").map(PathBuf::from);
+        }
+        if self.log_format.is_none() {
+            self.log_format = parse_env(&env, "LOG_FORMAT")?;
+        }
+        if self.log_format.is_none() {
+            self.log_format = parse_env(&env, "LOGGING_MODE")?;
+        }
+        if self.namespace.is_empty() {
+            if let Some(namespaces) = env("WATCH_NAMESPACES") {
+                self.namespace = namespaces
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+            }
+        }
+        if self.source_controller_url.is_none() {
+            self.source_controller_url = parse_env(&env, "SOURCE_CONTROLLER_URL")?;
+        }
+        if self.source_controller_ca_file.is_none() {
+            self.source_controller_ca_file = env("SOURCE_CONTROLLER_CA_FILE").map(PathBuf::from);
+        }
+        if self.source_controller_cert_file.is_none() {
+            self.source_controller_cert_file =
+                env("SOURCE_CONTROLLER_CERT_FILE").map(PathBuf::from);
+        }
+        if self.source_controller_key_file.is_none() {
+            self.source_controller_key_file = env("SOURCE_CONTROLLER_KEY_FILE").map(PathBuf::from);
+        }
+        if self.source_controller_proxy.is_none() {
+            self.source_controller_proxy = parse_env(&env, "SOURCE_CONTROLLER_PROXY")?;
+        }
+        if self.events_addr.is_none() {
+            self.events_addr = parse_env(&env, "EVENTS_ADDR")?;
+        }
+        if self.webhook_config.is_none() {
+            self.webhook_config = env("WEBHOOK_CONFIG").map(PathBuf::from);
+        }
+        if !self.dry_run {
+            self.dry_run = env("DRY_RUN").is_some_and(|v| v == "true");
+        }
+        if self.execution_backend.is_none() {
+            self.execution_backend = parse_env(&env, "EXECUTION_BACKEND")?;
+        }
+        if self.job_namespace.is_none() {
+            self.job_namespace = env("JOB_NAMESPACE");
+        }
+        if self.job_image.is_none() {
+            self.job_image = env("JOB_IMAGE");
+        }
+        if self.job_service_account.is_none() {
+            self.job_service_account = env("JOB_SERVICE_ACCOUNT");
+        }
+        if self.job_kubeconfig_secret.is_none() {
+            self.job_kubeconfig_secret = env("JOB_KUBECONFIG_SECRET");
+        }
+        if self.log_history.is_none() {
+            self.log_history = parse_env(&env, "LOG_HISTORY")?;
+        }
+        if self.namespace_selector.is_none() {
+            self.namespace_selector = env("WATCH_NAMESPACE_SELECTOR");
+        }
+        if self.watch_label_selector.is_none() {
+            self.watch_label_selector = env("WATCH_LABEL_SELECTOR");
+        }
+        if !self.leader_election {
+            self.leader_election = env("LEADER_ELECTION").is_some_and(|v| v == "true");
+        }
+        if self.leader_election_namespace.is_none() {
+            self.leader_election_namespace = env("LEADER_ELECTION_NAMESPACE");
+        }
+        if self.leader_election_id.is_none() {
+            self.leader_election_id = env("LEADER_ELECTION_ID");
+        }
+        if self.redact_pattern.is_empty() {
+            if let Some(patterns) = env("REDACT_PATTERNS") {
+                self.redact_pattern = patterns
+                    .lines()
+                    .filter(|p| !p.trim().is_empty())
+                    .map(|p| {
+                        Regex::new(p).map_err(|e| {
+                            Error::Configuration(format!("Invalid value for REDACT_PATTERNS: {e}"))
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Fills options that are neither set by flags nor environment variables from the config file
+    fn with_file(mut self, file: Config) -> Self {
+        // destructured so new options can not be forgotten here
+        let Config {
+            config_file: _,
+            concurrency,
+            bind_address,
+            requeue_interval,
+            requeue_error_interval,
+            requeue_pending_interval,
+            default_timeout,
+            shutdown_timeout,
+            temp_dir,
+            cache_dir,
+            log_format,
+            namespace,
+            namespace_selector,
+            source_controller_url,
+            source_controller_ca_file,
+            source_controller_cert_file,
+            source_controller_key_file,
+            source_controller_proxy,
+            events_addr,
+            webhook_config,
+            dry_run,
+            execution_backend,
+            job_namespace,
+            job_image,
+            job_service_account,
+            job_kubeconfig_secret,
+            log_history,
+            watch_label_selector,
+            leader_election,
+            leader_election_namespace,
+            leader_election_id,
+            redact_pattern,
+        } = file;
+        macro_rules! fill {
+            ($($option:ident),*) => {
+                $(if self.$option.is_none() {
+                    self.$option = $option;
+                })*
+            };
+        }
+        fill!(
+            concurrency,
+            bind_address,
+            requeue_interval,
+            requeue_error_interval,
+            requeue_pending_interval,
+            default_timeout,
+            shutdown_timeout,
+            temp_dir,
+            cache_dir,
+            log_format,
+            namespace_selector,
+            source_controller_url,
+            source_controller_ca_file,
+            source_controller_cert_file,
+            source_controller_key_file,
+            source_controller_proxy,
+            events_addr,
+            webhook_config,
+            execution_backend,
+            job_namespace,
+            job_image,
+            job_service_account,
+            job_kubeconfig_secret,
+            log_history,
+            watch_label_selector,
+            leader_election_namespace,
+            leader_election_id
+        );
+        self.dry_run |= dry_run;
+        self.leader_election |= leader_election;
+        if self.namespace.is_empty() {
+            self.namespace = namespace;
+        }
+        if self.redact_pattern.is_empty() {
+            self.redact_pattern = redact_pattern;
+        }
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.concurrency == Some(0) {
+            return Err(Error::Configuration(
+                "concurrency must be at least 1".to_owned(),
+            ));
+        }
+        for (name, interval) in [
+            ("requeue-interval", self.requeue_interval),
+            ("requeue-error-interval", self.requeue_error_interval),
+            ("requeue-pending-interval", self.requeue_pending_interval),
+            ("default-timeout", self.default_timeout),
+            ("shutdown-timeout", self.shutdown_timeout),
+        ] {
+            if interval == Some(Duration::ZERO) {
+                return Err(Error::Configuration(format!("{name} must not be zero")));
+            }
+        }
+        for (name, dir) in [("temp-dir", &self.temp_dir), ("cache-dir", &self.cache_dir)] {
+            if let Some(dir) = dir.as_ref().filter(|d| !d.is_dir()) {
+                return Err(Error::Configuration(format!(
+                    "{name} {dir:?} is not an existing directory"
+                )));
+            }
+        }
+        if self.namespace.iter().any(|n| n.trim().is_empty()) {
+            return Err(Error::Configuration(
+                "watched namespaces must not be empty".to_owned(),
+            ));
+        }
+        if !self.namespace.is_empty() && self.namespace_selector.is_some() {
+            return Err(Error::Configuration(
+                "namespace and namespace-selector can not be set together".to_owned(),
+            ));
+        }
+        if self.execution_backend == Some(ExecutionBackend::Job) && self.job_image.is_none() {
+            return Err(Error::Configuration(
+                "job-image must be set for the job execution backend".to_owned(),
+            ));
+        }
+        if self.source_controller_cert_file.is_some() != self.source_controller_key_file.is_some() {
+            return Err(Error::Configuration(
+                "source-controller client certificate and key must be set together".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> Result<Config> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| Error::Configuration(format!("Could not read config file {path:?}: {e}")))?;
+    serde_yaml::from_str(&content)
+        .map_err(|e| Error::Configuration(format!("Invalid config file {path:?}: {e}")))
+}
+
+fn parse_env<T: std::str::FromStr>(
+    env: &impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    env(key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| Error::Configuration(format!("Invalid value for {key}: {e}")))
+        })
+        .transpose()
+}
+
+fn parse_duration_arg(value: &str) -> std::result::Result<Duration, String> {
+    parse_duration::parse(value).map_err(|e| format!("invalid duration '{value}': {e}"))
+}
+
+fn duration_env(env: &impl Fn(&str) -> Option<String>, key: &str) -> Result<Option<Duration>> {
+    env(key)
+        .map(|value| {
+            parse_duration_arg(&value)
+                .map_err(|e| Error::Configuration(format!("Invalid value for {key}: {e}")))
+        })
+        .transpose()
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_duration_arg(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Deserializes a string the same way as the flag is parsed
+fn deserialize_parsed<'de, D: Deserializer<'de>, T: std::str::FromStr>(
+    deserializer: D,
+) -> std::result::Result<Option<T>, D::Error>
+where
+    T::Err: std::fmt::Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<Regex>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|p| Regex::new(p).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_env_fallback() {
+        let env = HashMap::from([
+            ("SOURCE_CONTROLLER_URL", "https://localhost:8443"),
+            ("SOURCE_CONTROLLER_PROXY", "http://proxy:3128"),
+        ]);
+        let config = Config {
+            source_controller_proxy: Some(Url::parse("http://other:3128").unwrap()),
+            ..Default::default()
+        }
+        .with_env(|key| env.get(key).map(|v| v.to_string()))
+        .unwrap();
+        assert_eq!(
+            config.source_controller_url.unwrap().as_str(),
+            "https://localhost:8443/"
+        );
+        assert_eq!(
+            config.source_controller_proxy.unwrap().as_str(),
+            "http://other:3128/"
+        );
+    }
+
+    #[test]
+    fn test_client_cert_requires_key() {
+        let config = Config {
+            source_controller_cert_file: Some(PathBuf::from("tls.crt")),
+            ..Default::default()
+        }
+        .resolve(|_| None);
+        assert!(matches!(config, Err(Error::Configuration(_))));
+        let config = Config::default().resolve(|key| match key {
+            "SOURCE_CONTROLLER_URL" => Some("not a url".to_owned()),
+            _ => None,
+        });
+        assert!(matches!(config, Err(Error::Configuration(_))));
+    }
+
+    #[test]
+    fn test_config_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            "concurrency: 8\nrequeueInterval: 10m\nlogFormat: json\nnamespace: [team-a, team-b]\ndryRun: true\nredactPattern: ['password=\\S+']\n",
+        )
+        .unwrap();
+        let config = Config {
+            concurrency: Some(4),
+            ..Default::default()
+        }
+        .resolve(|key| match key {
+            "CONFIG_FILE" => Some(file.path().to_str().unwrap().to_owned()),
+            "REQUEUE_INTERVAL" => Some("1m".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+        // flags win over env variables, env variables over the file
+        assert_eq!(config.concurrency(), 4);
+        assert_eq!(config.requeue_interval(), Duration::from_secs(60));
+        assert_eq!(config.log_format, Some(LogFormat::Json));
+        assert_eq!(config.namespace, vec!["team-a", "team-b"]);
+        assert!(config.dry_run);
+        assert_eq!(config.redact_pattern.len(), 1);
+        assert_eq!(config.requeue_error_interval(), Duration::from_secs(30));
+
+        std::fs::write(file.path(), "concurency: 8\n").unwrap();
+        let config = Config::default().resolve(|key| match key {
+            "CONFIG_FILE" => Some(file.path().to_str().unwrap().to_owned()),
+            _ => None,
+        });
+        assert!(matches!(config, Err(Error::Configuration(_))));
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config::default().resolve(|key| match key {
+            "CONCURRENCY" => Some("0".to_owned()),
+            _ => None,
+        });
+        assert!(matches!(config, Err(Error::Configuration(_))));
+        let config = Config::default().resolve(|key| match key {
+            "TEMP_DIR" => Some("/does/not/exist".to_owned()),
+            _ => None,
+        });
+        assert!(matches!(config, Err(Error::Configuration(_))));
+        let config = Config::default().resolve(|key| match key {
+            "DEFAULT_TIMEOUT" => Some("ten minutes".to_owned()),
+            _ => None,
+        });
+        assert!(matches!(config, Err(Error::Configuration(_))));
+        let config = Config {
+            namespace: vec!["team-a".to_owned()],
+            ..Default::default()
+        }
+        .resolve(|key| match key {
+            "WATCH_NAMESPACE_SELECTOR" => Some("tenant=team-a".to_owned()),
+            _ => None,
+        });
+        assert!(matches!(config, Err(Error::Configuration(_))));
+        assert!(Config::default().resolve(|_| None).is_ok());
+    }
+}
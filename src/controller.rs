@@ -1,18 +1,28 @@
 use super::util::map_finalizer_error;
+use crate::api::{Liveness, Readiness};
+use crate::config::Config;
 use crate::crd::{Helmfile, SourceRefKind};
 use crate::error::{Error, Result};
 use crate::extcrds::gitrepositories::GitRepository;
 use crate::flux::artifact::FluxSourceAdapterImpl;
+use crate::flux::events::FluxEventForwarder;
 use crate::helmfile::HelmfileAdapterImpl;
 use crate::k8sclient::K8sClientImpl;
 use crate::metrics::{
-    l, NUM_CLEANUPS_FAILED, NUM_CLEANUPS_STARTED, NUM_RECONCILES_FAILED, NUM_RECONCILES_PENDING,
-    NUM_RECONCILES_STARTED,
+    self, l, NUM_CLEANUPS_FAILED, NUM_CLEANUPS_STARTED, NUM_RECONCILES_FAILED,
+    NUM_RECONCILES_PENDING, NUM_RECONCILES_STARTED, RECONCILE_DURATION,
 };
-use crate::reconciler::{cleanup_helmfile, reconcile_helmfile, ReconcileResult};
+use crate::reconciler::{
+    cleanup_helmfile, reconcile_helmfile, Notifiers, ReconcileResult, APPROVED_REVISION_ANNOTATION,
+};
+use crate::redact;
 use crate::store::{ControllerStoreRef, NamespacedName};
 use crate::util::NS;
-use futures::StreamExt;
+use crate::webhooks::WebhookNotifier;
+use futures::stream::BoxStream;
+use futures::{future, Future, FutureExt, StreamExt};
+use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::NamespaceResourceScope;
 use kube::runtime::finalizer::Event as Finalizer;
 use kube::runtime::reflector::ObjectRef;
 use kube::runtime::{controller, finalizer, reflector, WatchStreamExt};
@@ -21,45 +31,225 @@ use kube::runtime::{
     watcher,
 };
 use kube::{Api, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
 use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 static FINALIZER: &str = "flux.maibornwolff.de";
-static REQUEUE_ERROR_SECONDS: u64 = 30;
-static REQUEUE_DEFAULT_SECONDS: u64 = 300;
-static REQUEUE_PENDING_SECONDS: u64 = 10;
-
-pub async fn run(client: Client, store: ControllerStoreRef) {
-    let context = Arc::new(Context {
-        client: client.clone(),
-        store: store.clone(),
-    });
-    let api = Api::<Helmfile>::all(client.clone());
-    let api_repo = Api::<GitRepository>::all(client.clone());
+/// How often the controller loop reports to the liveness watchdog
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Runs the controller until the shutdown future resolves, reconciles that are already running
+/// are finished first
+pub async fn run(
+    mut context: Context,
+    readiness: Readiness,
+    shutdown: impl Future<Output = ()> + Send + Sync + 'static,
+) {
+    let client = context.client.clone();
+    let store = context.store.clone();
+    let liveness = context.liveness.clone();
+
+    // namespaces matching the namespace selector, without one all namespaces are selected
+    let (selected_namespaces, changed_namespaces) =
+        match context.config.namespace_selector.as_deref() {
+            Some(selector) => {
+                let (reader, writer) = reflector::store();
+                let changed = watcher(
+                    Api::<Namespace>::all(client.clone()),
+                    watcher::Config::default().labels(selector),
+                )
+                .inspect(probe_watch("namespaces", &readiness, &liveness))
+                .reflect(writer)
+                .default_backoff()
+                .touched_objects();
+                (Some(reader), Some(changed))
+            }
+            None => (None, None),
+        };
+    context.selected_namespaces = selected_namespaces.clone();
+    let context = Arc::new(context);
+    // sharding: every instance only watches the objects matching its label selector
+    let watcher_config = match context.config.watch_label_selector.as_deref() {
+        Some(selector) => watcher::Config::default().labels(selector),
+        None => watcher::Config::default(),
+    };
 
     let (reader, writer) = reflector::store();
-    let changed_helmfiles = watcher(api, watcher::Config::default())
-        .reflect(writer)
+    let namespaces = &context.config.namespace;
+    let changed_helmfiles =
+        watch_namespaces::<Helmfile>(&client, namespaces, watcher_config.clone())
+            .inspect(probe_watch("helmfiles", &readiness, &liveness))
+            .reflect(writer)
+            .default_backoff()
+            .touched_objects()
+            .predicate_filter(predicate_filter)
+            .filter(move |obj| {
+                future::ready(match obj {
+                    Ok(obj) => watch_selected(selected_namespaces.as_ref(), obj),
+                    Err(_) => true,
+                })
+            });
+    let changed_repos = watch_namespaces::<GitRepository>(&client, namespaces, watcher_config)
+        .inspect(probe_watch("gitrepositories", &readiness, &liveness))
         .default_backoff()
-        .touched_objects()
-        .predicate_filter(predicate_filter);
+        .touched_objects();
 
-    Controller::for_stream(changed_helmfiles, reader)
-        .with_config(controller::Config::default().concurrency(2))
-        .watches(api_repo, watcher::Config::default(), move |repo| {
+    let helmfiles = reader.clone();
+    let mut controller = Controller::for_stream(changed_helmfiles, reader)
+        .with_config(controller::Config::default().concurrency(context.config.concurrency()))
+        .watches_stream(changed_repos, move |repo| {
             tokio::task::block_in_place(|| map_repo(repo, store.clone()))
-        })
-        .shutdown_on_signal()
+        });
+    if let Some(changed_namespaces) = changed_namespaces {
+        // a namespace that starts matching the selector gets all its helmfiles reconciled
+        controller = controller.watches_stream(changed_namespaces, move |namespace| {
+            let name = namespace.name_any();
+            helmfiles
+                .state()
+                .into_iter()
+                .filter(|obj| obj.namespace().as_deref() == Some(name.as_str()))
+                .map(|obj| ObjectRef::from_obj(obj.as_ref()))
+                .collect::<Vec<_>>()
+        });
+    }
+    let shutdown = shutdown.shared();
+    let controller = controller
+        .graceful_shutdown_on(shutdown.clone())
         .run(reconcile_with_finalizer, error_policy, context)
         .for_each(|res| async move {
             match res {
                 Ok(_) => (),
                 Err(e) => tracing::warn!("reconcile failed: {:?}", e),
             }
+        });
+    // the heartbeat is polled by the same task as the controller, so it stops when the loop
+    // blocks or exits. Silently stalled watches end in a read timeout and count as failing.
+    let heartbeat = async {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            liveness.heartbeat();
+        }
+    };
+    tokio::select! {
+        _ = controller => (),
+        _ = heartbeat => (),
+    }
+    if shutdown.peek().is_none() {
+        tracing::error!("Controller loop exited without a shutdown");
+        liveness.loop_exited();
+    }
+}
+
+/// Watches the objects in the given namespaces, or in all namespaces if none are given
+fn watch_namespaces<K>(
+    client: &Client,
+    namespaces: &[String],
+    config: watcher::Config,
+) -> BoxStream<'static, watcher::Result<watcher::Event<K>>>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + DeserializeOwned
+        + Debug
+        + Send
+        + Sync
+        + 'static,
+{
+    if namespaces.is_empty() {
+        return watcher(Api::all(client.clone()), config).boxed();
+    }
+    // every namespace is reflected into its own store, so a relist of one namespace can be
+    // passed on as a relist of all of them without dropping the objects of the others
+    let mut readers: Vec<reflector::Store<K>> = Vec::new();
+    let streams: Vec<_> = namespaces
+        .iter()
+        .map(|namespace| {
+            let (reader, writer) = reflector::store();
+            readers.push(reader);
+            watcher(Api::namespaced(client.clone(), namespace), config.clone())
+                .reflect(writer)
+                .boxed()
         })
-        .await;
+        .collect();
+    merge_watches(streams, readers)
+}
+
+/// Merges the watches of several namespaces. The first relist is only passed on once every
+/// namespace has been listed, so the merged watch is not synced before all of them are.
+fn merge_watches<K>(
+    streams: Vec<BoxStream<'static, watcher::Result<watcher::Event<K>>>>,
+    readers: Vec<reflector::Store<K>>,
+) -> BoxStream<'static, watcher::Result<watcher::Event<K>>>
+where
+    K: Resource<DynamicType = ()> + Clone + Send + Sync + 'static,
+{
+    let mut listed = vec![false; streams.len()];
+    let streams = streams
+        .into_iter()
+        .enumerate()
+        .map(|(index, stream)| stream.map(move |event| (index, event)));
+    futures::stream::select_all(streams)
+        .filter_map(move |(index, event)| {
+            future::ready(match event {
+                Ok(watcher::Event::Restarted(_)) => {
+                    listed[index] = true;
+                    listed.iter().all(|listed| *listed).then(|| {
+                        Ok(watcher::Event::Restarted(
+                            readers
+                                .iter()
+                                .flat_map(|reader| reader.state())
+                                .map(|obj| obj.as_ref().clone())
+                                .collect(),
+                        ))
+                    })
+                }
+                event => Some(event),
+            })
+        })
+        .boxed()
+}
+
+/// Whether the namespace of the object matches the namespace selector, always true without one
+fn namespace_selected(selected: Option<&reflector::Store<Namespace>>, obj: &Helmfile) -> bool {
+    let namespace = obj.namespace().unwrap_or_else(|| NS.to_owned());
+    match selected {
+        Some(store) => store.get(&ObjectRef::new(&namespace)).is_some(),
+        None => true,
+    }
+}
+
+/// Whether a watch event of the object is passed on, deletions are never dropped so the
+/// finalizer is removed even if the namespace no longer matches the selector
+fn watch_selected(selected: Option<&reflector::Store<Namespace>>, obj: &Helmfile) -> bool {
+    obj.meta().deletion_timestamp.is_some() || namespace_selected(selected, obj)
+}
+
+/// Marks a watch as ready once its initial list has been synced and reports watch errors to the watchdog
+fn probe_watch<K>(
+    kind: &'static str,
+    readiness: &Readiness,
+    liveness: &Liveness,
+) -> impl FnMut(&watcher::Result<watcher::Event<K>>) {
+    readiness.set(kind, Some("initial list not yet synced".to_owned()));
+    let readiness = readiness.clone();
+    let liveness = liveness.clone();
+    move |event| match event {
+        Ok(watcher::Event::Restarted(_)) => {
+            readiness.set(kind, None);
+            liveness.watch_succeeded(kind);
+        }
+        Ok(_) => liveness.watch_succeeded(kind),
+        Err(err) => {
+            tracing::debug!("Watch of {kind} failed: {err}");
+            liveness.watch_failed(kind);
+        }
+    }
 }
 
 fn predicate_filter(obj: &Helmfile) -> Option<u64> {
@@ -76,6 +266,10 @@ fn predicate_filter(obj: &Helmfile) -> Option<u64> {
     if let Some(uid) = obj.meta().uid.as_ref() {
         uid.hash(&mut hasher);
     }
+    // approving a revision only sets the annotation
+    if let Some(approved) = obj.annotations().get(APPROVED_REVISION_ANNOTATION) {
+        approved.hash(&mut hasher);
+    }
     Some(hasher.finish())
 }
 
@@ -84,17 +278,39 @@ fn predicate_filter(obj: &Helmfile) -> Option<u64> {
 pub struct Context {
     pub client: Client,
     pub store: ControllerStoreRef,
+    pub config: Config,
+    pub flux_adapter: FluxSourceAdapterImpl,
+    pub helmfile_adapter: HelmfileAdapterImpl,
+    pub events: Option<FluxEventForwarder>,
+    pub webhooks: Option<WebhookNotifier>,
+    pub liveness: Liveness,
+    /// namespaces matching the namespace selector, set by run
+    pub selected_namespaces: Option<reflector::Store<Namespace>>,
 }
 
 async fn reconcile_with_finalizer(obj: Arc<Helmfile>, ctx: Arc<Context>) -> Result<Action> {
     let ns = obj.namespace().unwrap_or_else(|| NS.to_owned());
     let api: Api<Helmfile> = Api::namespaced(ctx.client.clone(), &ns);
+    let key: NamespacedName = (&obj).into();
+    ctx.liveness.reconcile_started(key.clone());
 
-    finalizer(&api, FINALIZER, obj, |event| async {
+    let result = finalizer(&api, FINALIZER, obj, |event| async {
         match event {
+            // deleted helmfiles are always cleaned up, even if their namespace no longer matches
+            Finalizer::Apply(obj) if !namespace_selected(ctx.selected_namespaces.as_ref(), &obj) => {
+                tracing::debug!(
+                    "Skipping helmfile {} in namespace {ns}, the namespace does not match the selector",
+                    obj.name_any()
+                );
+                Ok(Action::await_change())
+            }
             Finalizer::Apply(obj) => {
                 let labels = l(&obj);
+                let start = Instant::now();
                 let result = reconcile(obj, ctx.clone()).await;
+                RECONCILE_DURATION
+                    .get_or_create(&labels)
+                    .observe(start.elapsed().as_secs_f64());
                 if result.is_err() {
                     NUM_RECONCILES_FAILED.get_or_create(&labels).inc();
                 }
@@ -111,7 +327,9 @@ async fn reconcile_with_finalizer(obj: Arc<Helmfile>, ctx: Arc<Context>) -> Resu
         }
     })
     .await
-    .map_err(map_finalizer_error)
+    .map_err(map_finalizer_error);
+    ctx.liveness.reconcile_finished(&key);
+    result
 }
 
 async fn reconcile(obj: Arc<Helmfile>, ctx: Arc<Context>) -> Result<Action> {
@@ -121,24 +339,32 @@ async fn reconcile(obj: Arc<Helmfile>, ctx: Arc<Context>) -> Result<Action> {
     let source_name = &obj.spec.source_ref.name;
     let source = get_gitrepository(ctx.client.clone(), &ns, source_name).await;
     if let Some(repo) = source {
+        let span = helmfile_span("reconcile", &obj);
         let result = reconcile_helmfile(
             K8sClientImpl::new(ctx.client.clone()),
-            HelmfileAdapterImpl {},
-            FluxSourceAdapterImpl {},
+            ctx.helmfile_adapter.clone(),
+            ctx.flux_adapter.clone(),
             ctx.store.clone(),
+            &ctx.config,
             &obj,
             repo,
+            Notifiers {
+                events: ctx.events.as_ref(),
+                webhooks: ctx.webhooks.as_ref(),
+            },
         )
-        .await?;
-        Ok(requeue_action(&obj.spec.interval, &result))
+        .instrument(span.clone())
+        .await;
+        ctx.helmfile_adapter.finish(&obj).await;
+        span.record("result", result_attribute(&result));
+        let result = result?;
+        Ok(requeue_action(&ctx.config, &obj.spec.interval, &result))
     } else {
         tracing::info!(
             "Could not yet find GitRepository {source_name} in namespace {ns}. Requeuing"
         );
         NUM_RECONCILES_PENDING.get_or_create(&l(&obj)).inc();
-        Ok(Action::requeue(Duration::from_secs(
-            REQUEUE_PENDING_SECONDS,
-        )))
+        Ok(Action::requeue(ctx.config.requeue_pending_interval()))
     }
 }
 
@@ -160,19 +386,57 @@ async fn cleanup(obj: Arc<Helmfile>, ctx: Arc<Context>) -> Result<Action> {
         let source_name = &obj.spec.source_ref.name;
         // see if gitrepo still exists
         let source = get_gitrepository(ctx.client.clone(), &ns, source_name).await;
-        cleanup_helmfile(
+        let span = helmfile_span("cleanup", &obj);
+        let result = cleanup_helmfile(
             K8sClientImpl::new(ctx.client.clone()),
-            HelmfileAdapterImpl {},
-            FluxSourceAdapterImpl {},
+            ctx.helmfile_adapter.clone(),
+            ctx.flux_adapter.clone(),
             ctx.store.clone(),
+            &ctx.config,
             &obj,
             source,
+            Notifiers {
+                events: ctx.events.as_ref(),
+                webhooks: ctx.webhooks.as_ref(),
+            },
         )
-        .await?;
+        .instrument(span.clone())
+        .await;
+        ctx.helmfile_adapter.finish(&obj).await;
+        span.record("result", result_attribute(&result));
+        result?;
     }
+    ctx.helmfile_adapter.logs().remove(&(&obj).into()).await;
+    redact::remove_secrets(&(&obj).into());
+    metrics::remove_state(&obj);
     Ok(Action::await_change())
 }
 
+/// Span for all events of a reconcile, the revision and result are recorded once they are known
+fn helmfile_span(operation: &str, obj: &Helmfile) -> tracing::Span {
+    tracing::info_span!(
+        "helmfile",
+        operation,
+        namespace = obj.namespace().unwrap_or_else(|| NS.to_owned()),
+        name = obj.name_any(),
+        revision = tracing::field::Empty,
+        result = tracing::field::Empty
+    )
+}
+
+fn result_attribute(result: &Result<ReconcileResult>) -> &'static str {
+    match result {
+        Ok(ReconcileResult::Success) => "success",
+        Ok(ReconcileResult::Failed(_)) => "failed",
+        Ok(ReconcileResult::FailedRetriesExhausted(_)) => "retries-exhausted",
+        Ok(ReconcileResult::Pending(_)) => "pending",
+        Ok(ReconcileResult::Suspended(_)) => "suspended",
+        Ok(ReconcileResult::AwaitingApproval(_)) => "awaiting-approval",
+        Ok(ReconcileResult::Interrupted) => "interrupted",
+        Err(_) => "error",
+    }
+}
+
 fn map_repo(repo: GitRepository, store: ControllerStoreRef) -> Vec<ObjectRef<Helmfile>> {
     let store = store.blocking_read();
     store
@@ -194,55 +458,180 @@ fn matches_gitrepository(name: &NamespacedName, obj: &Helmfile, repo: &GitReposi
         && name.namespace == repo.namespace().unwrap_or_else(|| NS.to_owned())
 }
 
-fn error_policy(_obj: Arc<Helmfile>, _error: &Error, _ctx: Arc<Context>) -> Action {
-    Action::requeue(Duration::from_secs(REQUEUE_ERROR_SECONDS))
+fn error_policy(_obj: Arc<Helmfile>, _error: &Error, ctx: Arc<Context>) -> Action {
+    Action::requeue(ctx.config.requeue_error_interval())
 }
 
 async fn get_gitrepository(client: Client, namespace: &str, name: &str) -> Option<GitRepository> {
     let api = Api::<GitRepository>::namespaced(client, namespace);
-    if let Ok(result) = api.get(name).await {
-        Some(result)
-    } else {
-        None
-    }
+    api.get(name).await.ok()
 }
 
-fn requeue_action(interval: &Option<String>, result: &ReconcileResult) -> Action {
+fn requeue_action(config: &Config, interval: &Option<String>, result: &ReconcileResult) -> Action {
     match result {
         ReconcileResult::Success => Action::requeue(if let Some(interval) = interval {
-            parse_duration::parse(interval)
-                .unwrap_or_else(|_| Duration::from_secs(REQUEUE_DEFAULT_SECONDS))
+            parse_duration::parse(interval).unwrap_or_else(|_| config.requeue_interval())
         } else {
-            Duration::from_secs(REQUEUE_DEFAULT_SECONDS)
+            config.requeue_interval()
         }),
-        ReconcileResult::Failed(_) => Action::requeue(Duration::from_secs(REQUEUE_ERROR_SECONDS)),
-        ReconcileResult::FailedRetriesExhausted(_) => Action::await_change(),
-        ReconcileResult::Pending(_) => {
-            Action::requeue(Duration::from_secs(REQUEUE_PENDING_SECONDS))
+        ReconcileResult::Failed(_) | ReconcileResult::Interrupted => {
+            Action::requeue(config.requeue_error_interval())
         }
+        ReconcileResult::FailedRetriesExhausted(_) => Action::await_change(),
+        // the watch of the GitRepository triggers a reconcile once it is resumed
+        ReconcileResult::Suspended(_) => Action::await_change(),
+        ReconcileResult::AwaitingApproval(_) => Action::await_change(),
+        ReconcileResult::Pending(_) => Action::requeue(config.requeue_pending_interval()),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::Utc;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use kube::core::ObjectMeta;
+    use std::time::Duration;
+
+    #[test]
+    fn test_namespace_selected() {
+        let obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("team-a".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(namespace_selected(None, &obj));
+
+        let (reader, mut writer) = reflector::store();
+        assert!(!namespace_selected(Some(&reader), &obj));
+        writer.apply_watcher_event(&watcher::Event::Applied(Namespace {
+            metadata: ObjectMeta {
+                name: Some("team-a".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        assert!(namespace_selected(Some(&reader), &obj));
+    }
+
+    #[test]
+    fn test_watch_selected() {
+        let mut obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("team-a".to_owned()),
+                finalizers: Some(vec![FINALIZER.to_owned()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (reader, _writer) = reflector::store::<Namespace>();
+        assert!(!watch_selected(Some(&reader), &obj));
+
+        // a deleted helmfile in a namespace that does not match still gets its cleanup
+        obj.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        assert!(watch_selected(Some(&reader), &obj));
+    }
+
+    #[tokio::test]
+    async fn test_merge_watches() {
+        let helmfile = |name: &str, namespace: &str| Helmfile {
+            metadata: ObjectMeta {
+                name: Some(name.to_owned()),
+                namespace: Some(namespace.to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let events = [
+            vec![Ok(watcher::Event::Restarted(vec![helmfile(
+                "foo", "team-a",
+            )]))],
+            vec![
+                Ok(watcher::Event::Applied(helmfile("bar", "team-a"))),
+                Ok(watcher::Event::Restarted(vec![helmfile("baz", "team-b")])),
+            ],
+        ];
+        let mut readers = Vec::new();
+        let streams = events
+            .into_iter()
+            .map(|events| {
+                let (reader, writer) = reflector::store();
+                readers.push(reader);
+                futures::stream::iter(events).reflect(writer).boxed()
+            })
+            .collect();
+
+        let merged: Vec<_> = merge_watches(streams, readers).collect().await;
+        // the relist of team-a is held back until team-b has been listed too
+        let restarted: Vec<_> = merged
+            .iter()
+            .filter_map(|event| match event {
+                Ok(watcher::Event::Restarted(objs)) => Some(objs.len()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(restarted, vec![2]);
+        assert!(matches!(
+            merged.last(),
+            Some(Ok(watcher::Event::Restarted(_)))
+        ));
+    }
+
+    #[test]
+    fn test_predicate_filter() {
+        let mut obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("bar".to_owned()),
+                generation: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let before = predicate_filter(&obj);
+        obj.annotations_mut()
+            .insert("unrelated".to_owned(), "value".to_owned());
+        assert_eq!(predicate_filter(&obj), before);
+        obj.annotations_mut().insert(
+            APPROVED_REVISION_ANNOTATION.to_owned(),
+            "main@sha1:1234".to_owned(),
+        );
+        assert_ne!(predicate_filter(&obj), before);
+    }
 
     #[test]
     fn test_requeue_interval() {
+        let config = Config {
+            requeue_interval: Some(Duration::from_secs(600)),
+            ..Default::default()
+        };
         assert_eq!(
-            requeue_action(&Some("2m30s".to_string()), &ReconcileResult::Success),
+            requeue_action(
+                &config,
+                &Some("2m30s".to_string()),
+                &ReconcileResult::Success
+            ),
             Action::requeue(Duration::from_secs(2 * 60 + 30))
         );
         assert_eq!(
-            requeue_action(&None, &ReconcileResult::Success),
-            Action::requeue(Duration::from_secs(REQUEUE_DEFAULT_SECONDS))
+            requeue_action(&config, &None, &ReconcileResult::Success),
+            Action::requeue(Duration::from_secs(600))
         );
         assert_eq!(
-            requeue_action(&None, &ReconcileResult::Failed("foobar".to_string())),
-            Action::requeue(Duration::from_secs(REQUEUE_ERROR_SECONDS))
+            requeue_action(
+                &config,
+                &None,
+                &ReconcileResult::Failed("foobar".to_string())
+            ),
+            Action::requeue(Duration::from_secs(30))
         );
         assert_eq!(
             requeue_action(
+                &config,
                 &None,
                 &ReconcileResult::FailedRetriesExhausted("foobar".to_string())
             ),
@@ -250,10 +639,31 @@ mod tests {
         );
         assert_eq!(
             requeue_action(
+                &config,
+                &Some("10s".to_string()),
+                &ReconcileResult::AwaitingApproval("main@sha1:1234".to_string())
+            ),
+            Action::await_change()
+        );
+        assert_eq!(
+            requeue_action(
+                &config,
+                &None,
+                &ReconcileResult::Suspended("source not ready".to_string())
+            ),
+            Action::await_change()
+        );
+        assert_eq!(
+            requeue_action(
+                &config,
                 &Some("10s".to_string()),
                 &ReconcileResult::Failed("foobar".to_string())
             ),
-            Action::requeue(Duration::from_secs(REQUEUE_ERROR_SECONDS))
+            Action::requeue(Duration::from_secs(30))
+        );
+        assert_eq!(
+            requeue_action(&config, &None, &ReconcileResult::Interrupted),
+            Action::requeue(Duration::from_secs(30))
         );
     }
 }
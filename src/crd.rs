@@ -29,6 +29,20 @@ pub struct HelmfileSpec {
     pub options: Option<Options>,
     /// name of the serviceAccount to impersonate
     pub service_account_name: Option<String>,
+    /// periodic detection of drift between the cluster and the helmfile
+    pub drift_detection: Option<DriftDetection>,
+    /// require a manual approval before applying a new revision
+    pub approval: Option<Approval>,
+    /// if set to true the helmfile is only rendered and diffed, never applied or destroyed
+    pub dry_run: Option<bool>,
+    /// remediation of releases after a failed apply
+    pub remediation: Option<Remediation>,
+    /// set to releases to uninstall releases that were removed from the helmfile
+    pub prune: Option<PruneMode>,
+    /// if set to true wait for all objects of the releases to become ready after an apply
+    pub wait: Option<bool>,
+    /// objects to wait for after an apply
+    pub health_checks: Option<HealthChecks>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
@@ -64,6 +78,7 @@ pub enum DecryptionProviderKind {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct Options {
     /// timeout for running helmfile commands, will be aborted afterwards
     pub timeout: Option<String>,
@@ -71,14 +86,181 @@ pub struct Options {
     pub retries: Option<i32>,
     /// if set to true `helmfile destroy` will be run when the Helmfile object is deleted
     pub prune: Option<bool>,
+    /// if set to true the last artifact will not be applied while the source reports a failed verification
+    pub block_on_verification_failure: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+pub struct DriftDetection {
+    /// enabled runs `helmfile diff` and applies on drift, warn only reports drift, disabled always runs `helmfile apply`
+    pub mode: DriftDetectionMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftDetectionMode {
+    Enabled,
+    Warn,
+    #[default]
+    Disabled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+pub struct Approval {
+    /// if set to true new revisions are only applied after the `controller/approved-revision` annotation names them
+    pub required: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+pub struct Remediation {
+    /// rollback reverts releases changed by a failed apply to their previous helm revision
+    pub strategy: RemediationStrategy,
+    /// number of consecutive failed applies to remediate, negative means always, default is always
+    pub retries: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RemediationStrategy {
+    Rollback,
+    #[default]
+    None,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+pub struct HealthChecks {
+    /// how long to wait for the objects to become ready, default is 5m
+    pub timeout: Option<String>,
+    /// objects to check in addition to the objects of the releases if wait is set
+    #[serde(default)]
+    pub objects: Vec<HealthCheckRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheckRef {
+    pub api_version: String,
+    pub kind: String,
+    pub name: String,
+    /// namespace of the object, defaults to the namespace of the Helmfile object
+    pub namespace: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PruneMode {
+    Releases,
+    #[default]
+    None,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct DeploymentStatus {
     pub status: DeploymentResult,
     pub reason: Option<String>,
     pub last_update: String,
+    /// revision of the source artifact that was last applied successfully
+    pub last_applied_revision: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    /// planned changes of a revision waiting for approval or of a dry run
+    pub plan: Option<Plan>,
+    /// results of the last remediation after a failed apply
+    pub remediation: Option<RemediationStatus>,
+    /// releases deployed by the helmfile as of the last apply
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inventory: Vec<ReleaseInventoryEntry>,
+    /// releases garbage-collected during the last prune
+    pub pruned: Option<PruneStatus>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneStatus {
+    /// revision of the source artifact that no longer contained the releases
+    pub revision: String,
+    /// time of the prune
+    pub time: String,
+    pub releases: Vec<PrunedRelease>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PrunedRelease {
+    pub name: String,
+    pub namespace: String,
+    /// one of Uninstalled or UninstallFailed
+    pub result: String,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseInventoryEntry {
+    pub name: String,
+    pub namespace: String,
+    pub chart: String,
+    /// chart version as set in the helmfile
+    pub version: String,
+    /// current helm revision, none if the release is not installed
+    pub revision: Option<i64>,
+    /// helm status of the current revision, e.g. deployed or failed
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemediationStatus {
+    /// revision of the source artifact whose apply failed
+    pub revision: String,
+    /// time of the remediation
+    pub time: String,
+    /// results per release changed by the failed apply
+    pub releases: Vec<ReleaseRemediation>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseRemediation {
+    pub name: String,
+    pub namespace: String,
+    /// helm revision before the failed apply, none if the release was newly installed
+    pub previous_revision: Option<i64>,
+    /// helm revision after the failed apply
+    pub failed_revision: Option<i64>,
+    /// one of RolledBack, RollbackFailed or Skipped
+    pub result: String,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Plan {
+    /// revision of the source artifact the plan was created for
+    pub revision: String,
+    /// output of `helmfile diff`, truncated if too large
+    pub diff: String,
+    /// time the plan was created
+    pub created: String,
+    /// name of the ConfigMap holding the rendered manifests of a dry run
+    pub manifests_config_map: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    /// type of the condition, e.g. Drifted
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// status of the condition, one of True, False, Unknown
+    pub status: String,
+    /// machine readable reason for the last transition
+    pub reason: String,
+    /// human readable details
+    pub message: String,
+    /// last time the status of the condition changed
+    pub last_transition_time: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
@@ -88,6 +270,10 @@ pub enum DeploymentResult {
     Successful,
     #[default]
     Pending,
+    #[serde(rename = "awaiting-approval")]
+    AwaitingApproval,
+    /// the last run was terminated by a controller shutdown and is repeated
+    Interrupted,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, JsonSchema, Default)]
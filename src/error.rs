@@ -16,6 +16,10 @@ pub enum Error {
     MissingSecret(String),
     #[error("Error during handling of crypto keys: {0}")]
     CryptoHandling(String),
+    #[error("ConfigurationError: {0}")]
+    Configuration(String),
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,609 @@
+use super::{read_lines, terminated, ExecError, Executor, Invocation, Output};
+use crate::config::Config;
+use crate::crd::Helmfile;
+use crate::error::{Error, Result};
+use crate::store::NamespacedName;
+use crate::util::NS;
+use async_trait::async_trait;
+use chrono::Utc;
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::api::{AttachParams, DeleteParams, ListParams, PostParams, PropagationPolicy};
+use kube::{Api, Client, ResourceExt};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+use tokio::time;
+use url::Url;
+
+const CONTAINER: &str = "helmfile";
+const WORKSPACE_DIR: &str = "/workspace";
+const KEYS_DIR: &str = "/keys";
+const KUBECONFIG_DIR: &str = "/etc/kubeconfig";
+const KEY_FILE: &str = "key";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// removes jobs left behind by a crashed controller, longer than any reconcile
+const SESSION_DEADLINE: Duration = Duration::from_secs(2 * 60 * 60);
+/// time the commands get to exit after SIGTERM when the job is deleted
+const TERMINATION_GRACE: Duration = Duration::from_secs(30);
+const DEFAULT_SERVICE_ACCOUNT: &str = "helmfile-controller";
+const DEFAULT_NAMESPACE: &str = "flux-system";
+
+/// Runs the commands of a reconcile in the pod of a Kubernetes Job, so helmfile does not share
+/// the resources and limits of the controller pod. The job is started with the first command
+/// of a reconcile and deleted once the reconcile is finished.
+pub struct JobExecutor {
+    client: Client,
+    namespace: String,
+    image: String,
+    service_account: String,
+    kubeconfig_secret: Option<String>,
+    source_controller_url: Option<Url>,
+    /// becomes true when running commands have to be terminated, at the end of a shutdown
+    terminate: Option<watch::Receiver<bool>>,
+    /// jobs of the running reconciles
+    sessions: Mutex<HashMap<NamespacedName, Arc<tokio::sync::Mutex<Option<Session>>>>>,
+}
+
+/// Job that runs the commands of one reconcile
+struct Session {
+    job: String,
+    pod: String,
+    /// url of the artifact extracted into the workspace of the pod
+    artifact_url: Option<String>,
+    /// local key file copied into the pod
+    key_file: Option<String>,
+}
+
+impl JobExecutor {
+    pub fn new(
+        client: Client,
+        config: &Config,
+        terminate: Option<watch::Receiver<bool>>,
+    ) -> Result<Self> {
+        let image = config.job_image.clone().ok_or_else(|| {
+            Error::Configuration("job-image must be set for the job execution backend".to_owned())
+        })?;
+        Ok(Self {
+            client,
+            namespace: config
+                .job_namespace
+                .clone()
+                .unwrap_or_else(|| DEFAULT_NAMESPACE.to_owned()),
+            image,
+            service_account: config
+                .job_service_account
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SERVICE_ACCOUNT.to_owned()),
+            kubeconfig_secret: config.job_kubeconfig_secret.clone(),
+            source_controller_url: config.source_controller_url.clone(),
+            terminate,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Creates the job for a reconcile and waits until its pod is running
+    async fn start(&self, obj: &Helmfile, timeout: Duration) -> Result<Session, String> {
+        let jobs = Api::<Job>::namespaced(self.client.clone(), &self.namespace);
+        let name = job_name(&obj.name_any());
+        jobs.create(&PostParams::default(), &self.job(&name, obj))
+            .await
+            .map_err(|e| format!("could not create job: {e}"))?;
+        tracing::info!(
+            "Started job {name} in namespace {} for helmfile {}",
+            self.namespace,
+            obj.name_any()
+        );
+        let result = match time::timeout(timeout, self.wait_for_pod(&name)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("pod of job {name} did not start in time")),
+        };
+        match result {
+            Ok(pod) => Ok(Session {
+                job: name,
+                pod,
+                artifact_url: None,
+                key_file: None,
+            }),
+            Err(err) => {
+                self.delete_job(&name).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Waits for the container of the job to run and returns the name of its pod
+    async fn wait_for_pod(&self, job_name: &str) -> Result<String, String> {
+        let pods = Api::<Pod>::namespaced(self.client.clone(), &self.namespace);
+        let params = ListParams::default().labels(&format!("job-name={job_name}"));
+        loop {
+            let pods = pods
+                .list(&params)
+                .await
+                .map_err(|e| format!("could not list pods of job {job_name}: {e}"))?;
+            for pod in pods.items {
+                let state = container_state(&pod);
+                if state.as_ref().is_some_and(|s| s.running.is_some()) {
+                    return Ok(pod.name_any());
+                }
+                if state.is_some() || pod_phase(&pod) == "Failed" {
+                    return Err(format!(
+                        "job {job_name} failed before the commands could be run, see the logs of pod {}",
+                        pod.name_any()
+                    ));
+                }
+            }
+            time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Prepares the workspace in the pod and runs the command in it
+    async fn run(
+        &self,
+        session: &mut Session,
+        invocation: &Invocation,
+    ) -> Result<Output, ExecError> {
+        let mut working_dir = WORKSPACE_DIR.to_owned();
+        let mut env = Vec::new();
+        if let Some(workspace) = invocation.workspace.as_ref() {
+            if session.artifact_url.as_ref() != Some(&workspace.artifact_url) {
+                self.fetch_artifact(session, &workspace.artifact_url, invocation.timeout)
+                    .await
+                    .map_err(ExecError::Other)?;
+                session.artifact_url = Some(workspace.artifact_url.clone());
+            }
+            if let Some(path) = workspace.path.as_ref() {
+                working_dir = Path::new(WORKSPACE_DIR).join(path).display().to_string();
+            }
+            if let Some((key, path)) = workspace.key_file.as_ref() {
+                if session.key_file.as_ref() != Some(path) {
+                    let content = std::fs::read(path)
+                        .map_err(|e| ExecError::Other(format!("could not read key file: {e}")))?;
+                    self.copy_key(session, content)
+                        .await
+                        .map_err(ExecError::Other)?;
+                    session.key_file = Some(path.clone());
+                }
+                env.push(format!("{key}={KEYS_DIR}/{KEY_FILE}"));
+            }
+        }
+        let command = exec_command(&working_dir, &env, invocation);
+        let run = self.exec(
+            &session.pod,
+            command,
+            None,
+            invocation.program,
+            invocation.manifests,
+        );
+        match time::timeout(invocation.timeout, run).await {
+            Ok(result) => result.map_err(ExecError::Other),
+            Err(_) => Err(ExecError::Timeout),
+        }
+    }
+
+    /// Downloads the artifact from the source-controller into the workspace of the pod
+    async fn fetch_artifact(
+        &self,
+        session: &Session,
+        artifact_url: &str,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let mut url = artifact_url.to_owned();
+        if let (Ok(parsed), Some(base_url)) =
+            (Url::parse(&url), self.source_controller_url.as_ref())
+        {
+            url = crate::flux::artifact::rewrite_url(&parsed, base_url).to_string();
+        }
+        let script = format!("set -o pipefail; find {WORKSPACE_DIR} -mindepth 1 -delete && wget -q -O - \"$0\" | tar -xzf - -C {WORKSPACE_DIR}");
+        let fetch = self.exec(
+            &session.pod,
+            vec!["sh".to_owned(), "-c".to_owned(), script, url],
+            None,
+            "wget",
+            false,
+        );
+        let output = match time::timeout(timeout, fetch).await {
+            Ok(output) => output?,
+            Err(_) => {
+                return Err(format!(
+                    "could not fetch artifact in pod {} in time",
+                    session.pod
+                ))
+            }
+        };
+        if !output.success() {
+            return Err(format!(
+                "could not fetch artifact in pod {}: {}",
+                session.pod,
+                output.stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the decryption key into the memory backed key volume of the pod, it is passed
+    /// via stdin so it never shows up in the job spec or the exec request
+    async fn copy_key(&self, session: &Session, key: Vec<u8>) -> Result<(), String> {
+        let script = format!("umask 077; head -c \"$0\" > {KEYS_DIR}/{KEY_FILE}");
+        let command = vec![
+            "sh".to_owned(),
+            "-c".to_owned(),
+            script,
+            key.len().to_string(),
+        ];
+        let output = self
+            .exec(&session.pod, command, Some(key), "sh", false)
+            .await?;
+        if !output.success() {
+            return Err(format!(
+                "could not copy key file into pod {}: {}",
+                session.pod,
+                output.stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs a command in the container of the pod, stdout and stderr are read separately
+    async fn exec(
+        &self,
+        pod: &str,
+        command: Vec<String>,
+        stdin: Option<Vec<u8>>,
+        program: &str,
+        manifests: bool,
+    ) -> Result<Output, String> {
+        let pods = Api::<Pod>::namespaced(self.client.clone(), &self.namespace);
+        let params = AttachParams::default()
+            .container(CONTAINER)
+            .stdin(stdin.is_some());
+        let mut process = pods
+            .exec(pod, command, &params)
+            .await
+            .map_err(|e| format!("could not run {program} in pod {pod}: {e}"))?;
+        if let Some(input) = stdin {
+            let mut writer = process.stdin().expect("stdin is attached");
+            writer
+                .write_all(&input)
+                .await
+                .map_err(|e| format!("could not write to {program} in pod {pod}: {e}"))?;
+        }
+        let stdout = process.stdout().expect("stdout is attached");
+        let stderr = process.stderr().expect("stderr is attached");
+        let status = process.take_status().expect("status is only taken once");
+        let (stdout, mut stderr, status) = tokio::join!(
+            read_lines(stdout, program, "stdout", manifests),
+            read_lines(stderr, program, "stderr", false),
+            status
+        );
+        let Some(status) = status else {
+            return Err(format!(
+                "lost connection to pod {pod} while running {program}"
+            ));
+        };
+        let code = exit_code(&status);
+        if let (None, Some(message)) = (code, status.message.as_ref()) {
+            stderr.push_str(message);
+        }
+        Ok(Output {
+            code,
+            stdout,
+            stderr,
+        })
+    }
+
+    async fn delete_job(&self, name: &str) {
+        let jobs = Api::<Job>::namespaced(self.client.clone(), &self.namespace);
+        let params = DeleteParams {
+            propagation_policy: Some(PropagationPolicy::Background),
+            ..Default::default()
+        };
+        if let Err(err) = jobs.delete(name, &params).await {
+            tracing::warn!("Could not delete job {name}: {err}");
+        }
+    }
+
+    /// Deletes all jobs of the object, including one that was just created
+    async fn delete_jobs(&self, obj: &Helmfile) {
+        let jobs = Api::<Job>::namespaced(self.client.clone(), &self.namespace);
+        let params = DeleteParams {
+            propagation_policy: Some(PropagationPolicy::Background),
+            ..Default::default()
+        };
+        let selector = format!(
+            "flux.maibornwolff.de/helmfile-name={},flux.maibornwolff.de/helmfile-namespace={}",
+            obj.name_any(),
+            obj.namespace().unwrap_or_else(|| NS.to_owned())
+        );
+        if let Err(err) = jobs
+            .delete_collection(&params, &ListParams::default().labels(&selector))
+            .await
+        {
+            tracing::warn!(
+                "Could not delete jobs of helmfile {}: {err}",
+                obj.name_any()
+            );
+        }
+    }
+
+    fn session(&self, obj: &Helmfile) -> Arc<tokio::sync::Mutex<Option<Session>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.entry(obj.into()).or_default().clone()
+    }
+
+    fn job(&self, name: &str, obj: &Helmfile) -> Job {
+        let mut volumes = vec![
+            json!({"name": "workspace", "emptyDir": {}}),
+            json!({"name": "keys", "emptyDir": {"medium": "Memory"}}),
+        ];
+        let mut mounts = vec![
+            json!({"name": "workspace", "mountPath": WORKSPACE_DIR}),
+            json!({"name": "keys", "mountPath": KEYS_DIR}),
+        ];
+        let mut env = Vec::new();
+        if let Some(secret) = self.kubeconfig_secret.as_ref() {
+            volumes.push(json!({"name": "kubeconfig", "secret": {"secretName": secret}}));
+            mounts
+                .push(json!({"name": "kubeconfig", "mountPath": KUBECONFIG_DIR, "readOnly": true}));
+            env.push(
+                json!({"name": "KUBECONFIG", "value": format!("{KUBECONFIG_DIR}/kubeconfig")}),
+            );
+        }
+
+        let deadline = SESSION_DEADLINE.as_secs();
+        let grace = TERMINATION_GRACE.as_secs();
+        let labels = json!({
+            "app.kubernetes.io/managed-by": "flux-helmfile-controller",
+            "flux.maibornwolff.de/helmfile-name": obj.name_any(),
+            "flux.maibornwolff.de/helmfile-namespace": obj.namespace().unwrap_or_else(|| NS.to_owned()),
+        });
+        let job = json!({
+            "apiVersion": "batch/v1",
+            "kind": "Job",
+            "metadata": {
+                "name": name,
+                "namespace": self.namespace,
+                "labels": labels,
+            },
+            "spec": {
+                "backoffLimit": 0,
+                "activeDeadlineSeconds": deadline,
+                "ttlSecondsAfterFinished": 600,
+                "template": {
+                    "metadata": {"labels": labels},
+                    "spec": {
+                        "restartPolicy": "Never",
+                        "terminationGracePeriodSeconds": grace + 5,
+                        "serviceAccountName": self.service_account,
+                        "securityContext": {"runAsNonRoot": true, "fsGroup": 1000},
+                        "containers": [{
+                            "name": CONTAINER,
+                            "image": self.image,
+                            // keeps the pod running for the executed commands and passes SIGTERM on to them
+                            "command": ["sh", "-c", format!("trap 'kill -TERM -1; sleep {grace}; exit 0' TERM; sleep {deadline} & wait")],
+                            "workingDir": WORKSPACE_DIR,
+                            "env": env,
+                            "volumeMounts": mounts,
+                        }],
+                        "volumes": volumes,
+                    }
+                }
+            }
+        });
+        serde_json::from_value(job).expect("job spec is valid")
+    }
+}
+
+#[async_trait]
+impl Executor for JobExecutor {
+    async fn execute(&self, invocation: Invocation, obj: &Helmfile) -> Result<Output, ExecError> {
+        let session = self.session(obj);
+        let mut session = session.lock().await;
+        let run = async {
+            let running = match session.as_mut() {
+                Some(running) => running,
+                None => session.insert(
+                    self.start(obj, invocation.timeout)
+                        .await
+                        .map_err(ExecError::Other)?,
+                ),
+            };
+            self.run(running, &invocation).await
+        };
+        let result = tokio::select! {
+            result = run => result,
+            _ = terminated(self.terminate.clone()) => Err(ExecError::Interrupted),
+        };
+        match &result {
+            Err(ExecError::Interrupted) => {
+                // the job might have been created without being recorded in the session yet
+                session.take();
+                self.delete_jobs(obj).await;
+            }
+            Err(_) => {
+                // the command might still be running in the pod, the next one starts a new job
+                if let Some(failed) = session.take() {
+                    self.delete_job(&failed.job).await;
+                }
+            }
+            Ok(_) => (),
+        }
+        result
+    }
+
+    async fn finish(&self, obj: &Helmfile) {
+        let session = self.sessions.lock().unwrap().remove(&obj.into());
+        if let Some(session) = session {
+            if let Some(finished) = session.lock().await.take() {
+                self.delete_job(&finished.job).await;
+            }
+        }
+    }
+}
+
+/// Command that runs the invocation in the working directory with the additional environment
+fn exec_command(working_dir: &str, env: &[String], invocation: &Invocation) -> Vec<String> {
+    let mut command = vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        "cd \"$0\" && exec env \"$@\"".to_owned(),
+        working_dir.to_owned(),
+    ];
+    command.extend(env.iter().cloned());
+    command.push(invocation.program.to_owned());
+    command.extend(invocation.args.iter().cloned());
+    command
+}
+
+/// Exit code reported by the kubelet for an executed command
+fn exit_code(status: &Status) -> Option<i32> {
+    if status.status.as_deref() == Some("Success") {
+        return Some(0);
+    }
+    status
+        .details
+        .as_ref()?
+        .causes
+        .as_ref()?
+        .iter()
+        .find(|c| c.reason.as_deref() == Some("ExitCode"))?
+        .message
+        .as_ref()?
+        .parse()
+        .ok()
+}
+
+/// State of the helmfile container once it was started
+fn container_state(pod: &Pod) -> Option<k8s_openapi::api::core::v1::ContainerState> {
+    pod.status
+        .as_ref()?
+        .container_statuses
+        .as_ref()?
+        .iter()
+        .find(|c| c.name == CONTAINER)?
+        .state
+        .clone()
+        .filter(|s| s.running.is_some() || s.terminated.is_some())
+}
+
+fn pod_phase(pod: &Pod) -> &str {
+    pod.status
+        .as_ref()
+        .and_then(|s| s.phase.as_deref())
+        .unwrap_or_default()
+}
+
+/// Builds a unique job name, kept short enough for the pod name suffix
+fn job_name(name: &str) -> String {
+    let prefix: String = format!("helmfile-{name}")
+        .chars()
+        .map(|c| c.to_ascii_lowercase())
+        .take(44)
+        .collect();
+    let suffix = Utc::now().timestamp_micros() & 0xffffff;
+    format!("{}-{suffix:06x}", prefix.trim_end_matches('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::Workspace;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{StatusCause, StatusDetails};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_job_name() {
+        let name = job_name("My-Very-Long-Helmfile-Name-That-Exceeds-The-Limit");
+        assert!(name.starts_with("helmfile-my-very-long-helmfile-name-that-exc-"));
+        assert_eq!(name.len(), 51);
+    }
+
+    #[tokio::test]
+    async fn test_job_spec() {
+        let config = Config {
+            job_image: Some("controller:latest".to_owned()),
+            job_kubeconfig_secret: Some("kubeconfig".to_owned()),
+            ..Default::default()
+        };
+        // the client is never used to build the spec
+        let client =
+            Client::try_from(kube::Config::new("http://localhost:1".parse().unwrap())).unwrap();
+        let executor = JobExecutor::new(client, &config, None).unwrap();
+        let job = executor.job("helmfile-demo-000001", &Helmfile::default());
+        let spec = serde_json::to_value(&job).unwrap()["spec"].clone();
+        let pod = &spec["template"]["spec"];
+        assert_eq!(spec["activeDeadlineSeconds"], SESSION_DEADLINE.as_secs());
+        assert_eq!(pod["serviceAccountName"], DEFAULT_SERVICE_ACCOUNT);
+        let container = &pod["containers"][0];
+        assert_eq!(
+            container["env"],
+            json!([{"name": "KUBECONFIG", "value": "/etc/kubeconfig/kubeconfig"}])
+        );
+        // the decryption keys are only kept in memory, there is no secret for them
+        assert_eq!(
+            pod["volumes"][1],
+            json!({"name": "keys", "emptyDir": {"medium": "Memory"}})
+        );
+    }
+
+    #[test]
+    fn test_exec_command() {
+        let invocation = Invocation {
+            program: "helmfile",
+            args: vec!["list".to_owned(), "--output".to_owned(), "json".to_owned()],
+            workspace: Some(Workspace {
+                dir: PathBuf::from("/tmp/abc/deploy"),
+                path: Some("deploy".to_owned()),
+                ..Default::default()
+            }),
+            timeout: Duration::from_secs(600),
+            manifests: false,
+        };
+        assert_eq!(
+            exec_command(
+                "/workspace/deploy",
+                &["SOPS_AGE_KEY_FILE=/keys/key".to_owned()],
+                &invocation
+            ),
+            vec![
+                "sh",
+                "-c",
+                "cd \"$0\" && exec env \"$@\"",
+                "/workspace/deploy",
+                "SOPS_AGE_KEY_FILE=/keys/key",
+                "helmfile",
+                "list",
+                "--output",
+                "json"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let status = |status: &str, causes| Status {
+            status: Some(status.to_owned()),
+            details: Some(StatusDetails {
+                causes: Some(causes),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(exit_code(&status("Success", vec![])), Some(0));
+        let exit_code_cause = StatusCause {
+            reason: Some("ExitCode".to_owned()),
+            message: Some("2".to_owned()),
+            field: None,
+        };
+        assert_eq!(
+            exit_code(&status("Failure", vec![exit_code_cause])),
+            Some(2)
+        );
+        assert_eq!(exit_code(&status("Failure", vec![])), None);
+    }
+}
@@ -0,0 +1,174 @@
+use super::{read_lines, terminated, ExecError, Executor, Invocation, Output};
+use crate::crd::Helmfile;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio::time;
+
+/// time a command gets to exit after SIGTERM before it is killed
+const TERMINATION_GRACE: Duration = Duration::from_secs(30);
+
+/// Runs commands as child processes of the controller
+#[derive(Default)]
+pub struct LocalExecutor {
+    /// directory for the helm and helmfile caches, the defaults below $HOME otherwise
+    pub cache_dir: Option<PathBuf>,
+    /// becomes true when running commands have to be terminated, at the end of a shutdown
+    pub terminate: Option<watch::Receiver<bool>>,
+}
+
+/// Signals the process group of a command, so helm and its plugins get the signals as well.
+/// Kills the whole group if dropped before the command finished.
+struct ProcessGroup {
+    pgid: Option<i32>,
+}
+
+impl ProcessGroup {
+    fn signal(&self, signal: i32) {
+        if let Some(pgid) = self.pgid {
+            // SAFETY: kill has no memory safety requirements, a negative pid addresses the group
+            unsafe { libc::kill(-pgid, signal) };
+        }
+    }
+
+    /// Sends SIGTERM and kills the group if the command did not exit within the grace period
+    async fn terminate(self, child: &mut Child, grace: Duration) {
+        self.signal(libc::SIGTERM);
+        let _ = time::timeout(grace, child.wait()).await;
+    }
+
+    fn finished(&mut self) {
+        self.pgid = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.signal(libc::SIGKILL);
+    }
+}
+
+#[async_trait]
+impl Executor for LocalExecutor {
+    async fn execute(&self, invocation: Invocation, _obj: &Helmfile) -> Result<Output, ExecError> {
+        let mut cmd = Command::new(invocation.program);
+        // own process group, so the command can be stopped together with its children
+        cmd.process_group(0);
+        cmd.args(&invocation.args);
+        if let Some(cache_dir) = self.cache_dir.as_ref() {
+            cmd.env("HELM_CACHE_HOME", cache_dir.join("helm"));
+            cmd.env("HELMFILE_CACHE_HOME", cache_dir.join("helmfile"));
+        }
+        if let Some(workspace) = invocation.workspace {
+            cmd.current_dir(&workspace.dir);
+            if let Some((key, value)) = workspace.key_file {
+                cmd.env(key, value);
+            }
+        }
+
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = cmd.spawn().map_err(|e| ExecError::Other(e.to_string()))?;
+        let mut group = ProcessGroup {
+            pgid: child.id().map(|id| id as i32),
+        };
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let program = invocation.program;
+        let manifests = invocation.manifests;
+        let run = async {
+            let (stdout, stderr, status) = tokio::join!(
+                read_lines(stdout, program, "stdout", manifests),
+                read_lines(stderr, program, "stderr", false),
+                child.wait()
+            );
+            status.map(|status| Output {
+                code: status.code(),
+                stdout,
+                stderr,
+            })
+        };
+
+        let error = tokio::select! {
+            result = time::timeout(invocation.timeout, run) => match result {
+                Ok(Ok(output)) => {
+                    group.finished();
+                    return Ok(output);
+                }
+                Ok(Err(err)) => return Err(ExecError::Other(err.to_string())),
+                Err(_) => ExecError::Timeout,
+            },
+            _ = terminated(self.terminate.clone()) => ExecError::Interrupted,
+        };
+        group.terminate(&mut child, TERMINATION_GRACE).await;
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn invocation(args: &[&str], timeout: Duration) -> Invocation {
+        Invocation {
+            program: "sh",
+            args: args.iter().map(|a| a.to_string()).collect(),
+            workspace: None,
+            timeout,
+            manifests: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        let executor = LocalExecutor::default();
+        let obj = Helmfile::default();
+        let output = executor
+            .execute(
+                invocation(
+                    &["-c", "echo out; echo err >&2; exit 2"],
+                    Duration::from_secs(10),
+                ),
+                &obj,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.code, Some(2));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        let result = executor
+            .execute(
+                invocation(&["-c", "sleep 10"], Duration::from_millis(50)),
+                &obj,
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), ExecError::Timeout);
+    }
+
+    #[tokio::test]
+    async fn test_terminate() {
+        let (terminate, receiver) = watch::channel(false);
+        let executor = LocalExecutor {
+            terminate: Some(receiver),
+            ..Default::default()
+        };
+        let obj = Helmfile::default();
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("terminated");
+        // the shell and its child get SIGTERM, the trap records it
+        let script = format!(
+            "trap 'touch {}; exit 1' TERM; sleep 10 & wait",
+            marker.display()
+        );
+        let run = executor.execute(invocation(&["-c", &script], Duration::from_secs(10)), &obj);
+        let (result, _) = tokio::join!(run, async {
+            time::sleep(Duration::from_millis(200)).await;
+            terminate.send(true).unwrap();
+        });
+        assert_eq!(result.unwrap_err(), ExecError::Interrupted);
+        assert!(marker.exists());
+    }
+}
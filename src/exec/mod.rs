@@ -0,0 +1,130 @@
+pub mod job;
+pub mod local;
+
+use crate::crd::Helmfile;
+use crate::redact::redact;
+use async_trait::async_trait;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::watch;
+
+/// Location of a helmfile and everything needed to run helmfile commands for it
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    /// directory with the helmfile inside the extracted artifact
+    pub dir: PathBuf,
+    /// url of the artifact the directory was extracted from
+    pub artifact_url: String,
+    /// path of the directory relative to the artifact root
+    pub path: Option<String>,
+    /// environment variable pointing to a file with decryption keys
+    pub key_file: Option<(String, String)>,
+}
+
+/// Description of a command that was terminated because the controller shuts down
+pub const INTERRUPTED: &str = "interrupted by controller shutdown";
+
+/// Error of a command that could not be run to completion
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecError {
+    /// the command did not finish within its timeout
+    Timeout,
+    /// the command was terminated because the controller shuts down
+    Interrupted,
+    /// the command could not be run
+    Other(String),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::Timeout => write!(f, "timeout"),
+            ExecError::Interrupted => write!(f, "{INTERRUPTED}"),
+            ExecError::Other(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// A helmfile or helm command to run
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub program: &'static str,
+    pub args: Vec<String>,
+    /// workspace to run the command in, helm commands do not need one
+    pub workspace: Option<Workspace>,
+    pub timeout: Duration,
+    /// stdout is rendered manifests, which can contain decrypted Secrets
+    pub manifests: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Output {
+    /// exit code of the command, None if it was terminated by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Backend that runs the helmfile and helm processes
+#[async_trait]
+pub trait Executor: Send + Sync {
+    /// Runs the command to completion. Returns an error if the command could not be run, timed
+    /// out or was interrupted.
+    async fn execute(&self, invocation: Invocation, obj: &Helmfile) -> Result<Output, ExecError>;
+
+    /// Called once a reconcile or cleanup of the object is done, releases what the backend kept
+    /// for the commands of that run
+    async fn finish(&self, _obj: &Helmfile) {}
+}
+
+/// Resolves once running commands have to be terminated, never without a receiver
+async fn terminated(terminate: Option<watch::Receiver<bool>>) {
+    if let Some(mut terminate) = terminate {
+        if terminate.wait_for(|t| *t).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Reads the output of a command line by line and emits each line as a tracing event. Rendered
+/// manifests are not logged, a Secret can only be masked once its whole document was read.
+async fn read_lines(
+    reader: impl AsyncRead + Unpin,
+    program: &str,
+    stream: &str,
+    manifests: bool,
+) -> String {
+    let mut reader = BufReader::new(reader);
+    let mut output = String::new();
+    let mut line = Vec::new();
+    let mut lines = 0;
+    while let Ok(read) = reader.read_until(b'\n', &mut line).await {
+        if read == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        if !manifests {
+            tracing::info!(command = program, stream, "{}", redact(text.trim_end()));
+        }
+        output.push_str(&text);
+        lines += 1;
+        line.clear();
+    }
+    if manifests {
+        tracing::info!(
+            command = program,
+            stream,
+            "{lines} lines of rendered manifests"
+        );
+    }
+    output
+}
@@ -1,13 +1,49 @@
+use crate::config::Config;
 use crate::error::{Error, Result};
 use crate::extcrds::gitrepositories::GitRepositoryStatusArtifact;
 use crate::store::HelmfileState;
 use async_trait::async_trait;
 use bytes::Buf;
 use flate2::read::GzDecoder;
+use std::path::PathBuf;
 use tempfile::TempDir;
 use url::Url;
 
-pub struct FluxSourceAdapterImpl {}
+#[derive(Clone)]
+pub struct FluxSourceAdapterImpl {
+    client: reqwest::Client,
+    base_url: Option<Url>,
+    temp_dir: PathBuf,
+}
+
+impl FluxSourceAdapterImpl {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut builder = reqwest::Client::builder().use_rustls_tls();
+        if let Some(ca_file) = config.source_controller_ca_file.as_ref() {
+            let pem = std::fs::read(ca_file)?;
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let (Some(cert_file), Some(key_file)) = (
+            config.source_controller_cert_file.as_ref(),
+            config.source_controller_key_file.as_ref(),
+        ) {
+            let mut pem = std::fs::read(cert_file)?;
+            pem.push(b'\n');
+            pem.extend(std::fs::read(key_file)?);
+            builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+        }
+        if let Some(proxy) = config.source_controller_proxy.as_ref() {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+        }
+        Ok(Self {
+            client: builder.build()?,
+            base_url: config.source_controller_url.clone(),
+            temp_dir: config.temp_dir(),
+        })
+    }
+}
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
@@ -40,25 +76,15 @@ impl FluxSourceAdapter for FluxSourceAdapterImpl {
 
         let mut url: Url =
             Url::parse(&artifact.url).map_err(|e| Error::ArtifactDownload(e.to_string()))?;
-        if let Ok(override_host) = std::env::var("SOURCE_CONTROLLER_HOST") {
-            if let Some((host, port)) = override_host.split_once(':') {
-                url.set_host(Some(host))
-                    .map_err(|e| Error::ArtifactDownload(e.to_string()))?;
-                url.set_port(Some(port.parse().map_err(|_| {
-                    Error::ArtifactDownload("Could not parse port".to_string())
-                })?))
-                .map_err(|_| Error::ArtifactDownload("Could not set port".to_owned()))?;
-            } else {
-                url.set_host(Some(override_host.as_str()))
-                    .map_err(|e| Error::ArtifactDownload(e.to_string()))?;
-            }
+        if let Some(base_url) = self.base_url.as_ref() {
+            url = rewrite_url(&url, base_url);
         }
-        let result = reqwest::get(url).await?;
+        let result = self.client.get(url).send().await?;
         if !result.status().is_success() {
             return Err(Error::ArtifactDownload(result.status().to_string()));
         }
         // create temp location
-        let location = TempDir::new_in("tmp")?;
+        let location = TempDir::new_in(&self.temp_dir)?;
 
         // Extract artifact to tmp location
         let data = result.bytes().await?;
@@ -70,3 +96,36 @@ impl FluxSourceAdapter for FluxSourceAdapterImpl {
         Ok((location, digest))
     }
 }
+
+/// Moves an artifact url onto the configured source-controller base url,
+/// keeping the artifact path below any path prefix of the base url
+pub fn rewrite_url(url: &Url, base_url: &Url) -> Url {
+    let mut result = base_url.clone();
+    let prefix = base_url.path().trim_end_matches('/');
+    result.set_path(&format!("{prefix}{}", url.path()));
+    result.set_query(url.query());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_url() {
+        let url = Url::parse(
+            "http://source-controller.flux-system.svc.cluster.local./gitrepository/default/demo/abc.tar.gz",
+        )
+        .unwrap();
+        let rewritten = rewrite_url(&url, &Url::parse("https://localhost:8443").unwrap());
+        assert_eq!(
+            rewritten.as_str(),
+            "https://localhost:8443/gitrepository/default/demo/abc.tar.gz"
+        );
+        let rewritten = rewrite_url(&url, &Url::parse("http://proxy/source/").unwrap());
+        assert_eq!(
+            rewritten.as_str(),
+            "http://proxy/source/gitrepository/default/demo/abc.tar.gz"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::crd::Helmfile;
+use chrono::{SecondsFormat, Utc};
+use kube::runtime::events::{Event, EventType};
+use kube::{Resource, ResourceExt};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use url::Url;
+
+const REPORTING_CONTROLLER: &str = "helmfile-controller";
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(15);
+const QUEUE_SIZE: usize = 100;
+
+/// Forwards events to the flux notification-controller so they can be routed by its Alerts.
+/// Events are posted from a bounded queue in the background, so an unreachable
+/// notification-controller never blocks reconciles.
+#[derive(Clone)]
+pub struct FluxEventForwarder {
+    queue: mpsc::Sender<Value>,
+}
+
+impl FluxEventForwarder {
+    pub fn new(address: Url, instance: Option<String>) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .timeout(FORWARD_TIMEOUT)
+            .build()?;
+        let (queue, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(forward_all(client, address, instance, receiver));
+        Ok(Self { queue })
+    }
+
+    /// Queues an event for the notification-controller, never waits for delivery
+    pub fn forward(&self, obj: &Helmfile, event: &Event, revision: Option<&str>) {
+        let body = flux_event(obj, event, revision);
+        if let Err(err) = self.queue.try_send(body) {
+            tracing::warn!("Dropping event {} for flux: {err}", event.reason);
+        }
+    }
+}
+
+async fn forward_all(
+    client: reqwest::Client,
+    address: Url,
+    instance: Option<String>,
+    mut receiver: mpsc::Receiver<Value>,
+) {
+    while let Some(mut body) = receiver.recv().await {
+        if let Some(instance) = instance.as_ref() {
+            body["reportingInstance"] = json!(instance);
+        }
+        if let Err(err) = post(&client, &address, &body).await {
+            let reason = body["reason"].as_str().unwrap_or_default();
+            tracing::warn!("Could not forward event {reason} to flux: {err}");
+        }
+    }
+}
+
+async fn post(client: &reqwest::Client, address: &Url, body: &Value) -> Result<(), String> {
+    let response = client
+        .post(address.clone())
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "notification-controller responded with {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Builds an event in the format of the flux event api (event.toolkit.fluxcd.io/v1beta1)
+fn flux_event(obj: &Helmfile, event: &Event, revision: Option<&str>) -> Value {
+    let severity = match event.type_ {
+        EventType::Normal => "info",
+        EventType::Warning => "error",
+    };
+    let mut body = json!({
+        "involvedObject": {
+            "kind": Helmfile::kind(&()),
+            "apiVersion": Helmfile::api_version(&()),
+            "namespace": obj.namespace(),
+            "name": obj.name_any(),
+            "uid": obj.uid(),
+            "resourceVersion": obj.resource_version(),
+        },
+        "severity": severity,
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "message": event.note.clone().unwrap_or_default(),
+        "reason": event.reason,
+        "reportingController": REPORTING_CONTROLLER,
+    });
+    if let Some(revision) = revision {
+        // notification-controller drops metadata keys not prefixed with the api group
+        let key = format!("{}/revision", Helmfile::group(&()));
+        body["metadata"] = json!({ key: revision });
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use kube::core::ObjectMeta;
+
+    #[tokio::test]
+    async fn test_forward_event() {
+        // local stand-in for the notification-controller event endpoint
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Value>();
+        let app = Router::new().route(
+            "/",
+            post(move |Json(body): Json<Value>| async move {
+                sender.send(body).unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let forwarder = FluxEventForwarder::new(
+            Url::parse(&format!("http://{address}/")).unwrap(),
+            Some("controller-0".to_owned()),
+        )
+        .unwrap();
+        let obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("bar".to_owned()),
+                uid: Some("1234".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "ReconciliationFailed".to_owned(),
+            note: Some("Revision main@sha1:abcd failed: boom".to_owned()),
+            action: "Reconcile".to_owned(),
+            secondary: None,
+        };
+        forwarder.forward(&obj, &event, Some("main@sha1:abcd"));
+
+        let body = receiver.recv().await.unwrap();
+        assert_eq!(
+            body["involvedObject"],
+            json!({
+                "kind": "Helmfile",
+                "apiVersion": "flux.maibornwolff.de/v1alpha1",
+                "namespace": "bar",
+                "name": "foo",
+                "uid": "1234",
+                "resourceVersion": null,
+            })
+        );
+        assert_eq!(body["severity"], "error");
+        assert_eq!(body["reason"], "ReconciliationFailed");
+        assert_eq!(body["message"], "Revision main@sha1:abcd failed: boom");
+        assert_eq!(
+            body["metadata"],
+            json!({"flux.maibornwolff.de/revision": "main@sha1:abcd"})
+        );
+        assert_eq!(body["reportingController"], REPORTING_CONTROLLER);
+        assert_eq!(body["reportingInstance"], "controller-0");
+    }
+}
@@ -0,0 +1,296 @@
+use crate::crd::HealthCheckRef;
+use crate::k8sclient::K8sClient;
+use kube::api::DynamicObject;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Simplified kstatus of a kubernetes object
+#[derive(Debug, PartialEq)]
+pub enum ObjectStatus {
+    Current,
+    InProgress(String),
+    Failed(String),
+}
+
+/// Polls the objects until all of them are current. Returns the first object that failed
+/// or was still not ready when the timeout expired.
+pub async fn wait_for_objects(
+    client: &impl K8sClient,
+    objects: &[HealthCheckRef],
+    timeout: Duration,
+) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut in_progress = None;
+        for object in objects {
+            let status = match client.get_object(object).await {
+                Ok(Some(found)) => object_status(&object.kind, &found),
+                Ok(None) => ObjectStatus::InProgress("not found".to_owned()),
+                Err(err) => ObjectStatus::InProgress(format!("could not be read: {err}")),
+            };
+            match status {
+                ObjectStatus::Current => {}
+                ObjectStatus::Failed(message) => {
+                    return Err(format!("{} failed: {message}", describe(object)))
+                }
+                ObjectStatus::InProgress(message) => {
+                    in_progress = Some(format!("{} is not ready: {message}", describe(object)));
+                    break;
+                }
+            }
+        }
+        let Some(message) = in_progress else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(format!("timeout waiting for objects: {message}"));
+        }
+        tracing::debug!("{message}");
+        time::sleep(POLL_INTERVAL.min(deadline - now)).await;
+    }
+}
+
+/// Extracts references to all objects of a rendered helm manifest
+pub fn manifest_objects(manifest: &str, namespace: &str) -> Result<Vec<HealthCheckRef>, String> {
+    let mut objects = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(manifest) {
+        let value = serde_json::Value::deserialize(document)
+            .map_err(|e| format!("Could not parse manifest: {e}"))?;
+        let (Some(api_version), Some(kind), Some(name)) = (
+            value["apiVersion"].as_str(),
+            value["kind"].as_str(),
+            value["metadata"]["name"].as_str(),
+        ) else {
+            continue;
+        };
+        objects.push(HealthCheckRef {
+            api_version: api_version.to_owned(),
+            kind: kind.to_owned(),
+            name: name.to_owned(),
+            namespace: Some(
+                value["metadata"]["namespace"]
+                    .as_str()
+                    .unwrap_or(namespace)
+                    .to_owned(),
+            ),
+        });
+    }
+    Ok(objects)
+}
+
+pub fn describe(object: &HealthCheckRef) -> String {
+    match object.namespace.as_ref() {
+        Some(namespace) => format!("{}/{}/{}", object.kind, namespace, object.name),
+        None => format!("{}/{}", object.kind, object.name),
+    }
+}
+
+/// Computes the status of an object following the kstatus rules for the common workload kinds
+pub fn object_status(kind: &str, obj: &DynamicObject) -> ObjectStatus {
+    let spec = &obj.data["spec"];
+    let status = &obj.data["status"];
+    if let (Some(generation), Some(observed)) = (
+        obj.metadata.generation,
+        status["observedGeneration"].as_i64(),
+    ) {
+        if observed < generation {
+            return ObjectStatus::InProgress("latest generation not yet observed".to_owned());
+        }
+    }
+    let int = |value: &Value| value.as_i64().unwrap_or(0);
+
+    match kind {
+        "Deployment" => {
+            if let Some(progressing) = condition(status, "Progressing") {
+                if progressing.reason == "ProgressDeadlineExceeded" {
+                    return ObjectStatus::Failed(progressing.message.to_owned());
+                }
+            }
+            let replicas = spec["replicas"].as_i64().unwrap_or(1);
+            let updated = int(&status["updatedReplicas"]);
+            let available = int(&status["availableReplicas"]);
+            if updated < replicas {
+                in_progress(format!("{updated}/{replicas} replicas updated"))
+            } else if int(&status["replicas"]) > updated {
+                in_progress("old replicas are pending termination".to_owned())
+            } else if available < updated {
+                in_progress(format!("{available}/{replicas} replicas available"))
+            } else {
+                ObjectStatus::Current
+            }
+        }
+        "StatefulSet" => {
+            let replicas = spec["replicas"].as_i64().unwrap_or(1);
+            let ready = int(&status["readyReplicas"]);
+            if ready < replicas {
+                in_progress(format!("{ready}/{replicas} replicas ready"))
+            } else if spec["updateStrategy"]["type"] != "OnDelete"
+                && status["updateRevision"] != status["currentRevision"]
+            {
+                in_progress("rolling update in progress".to_owned())
+            } else {
+                ObjectStatus::Current
+            }
+        }
+        "DaemonSet" => {
+            let desired = int(&status["desiredNumberScheduled"]);
+            let updated = int(&status["updatedNumberScheduled"]);
+            let available = int(&status["numberAvailable"]);
+            if updated < desired {
+                in_progress(format!("{updated}/{desired} pods updated"))
+            } else if available < desired {
+                in_progress(format!("{available}/{desired} pods available"))
+            } else {
+                ObjectStatus::Current
+            }
+        }
+        "Job" => match (condition(status, "Complete"), condition(status, "Failed")) {
+            (Some(c), _) if c.status == "True" => ObjectStatus::Current,
+            (_, Some(c)) if c.status == "True" => ObjectStatus::Failed(c.message.to_owned()),
+            _ => in_progress("job not yet complete".to_owned()),
+        },
+        "Pod" => match status["phase"].as_str().unwrap_or_default() {
+            "Succeeded" => ObjectStatus::Current,
+            "Failed" => ObjectStatus::Failed("pod failed".to_owned()),
+            "Running" if condition(status, "Ready").is_some_and(|c| c.status == "True") => {
+                ObjectStatus::Current
+            }
+            phase => in_progress(format!("pod is {phase}")),
+        },
+        "PersistentVolumeClaim" => match status["phase"].as_str() {
+            Some("Bound") => ObjectStatus::Current,
+            _ => in_progress("claim not yet bound".to_owned()),
+        },
+        "Service"
+            if spec["type"] == "LoadBalancer"
+                && status["loadBalancer"]["ingress"]
+                    .as_array()
+                    .map_or(0, Vec::len)
+                    == 0 =>
+        {
+            in_progress("no load balancer ingress".to_owned())
+        }
+        _ => {
+            // generic objects report their status with the standard conditions
+            if let Some(c) = condition(status, "Stalled").filter(|c| c.status == "True") {
+                ObjectStatus::Failed(c.message.to_owned())
+            } else if let Some(c) = condition(status, "Ready").filter(|c| c.status == "False") {
+                in_progress(c.message.to_owned())
+            } else if condition(status, "Reconciling").is_some_and(|c| c.status == "True") {
+                in_progress("reconciling".to_owned())
+            } else {
+                ObjectStatus::Current
+            }
+        }
+    }
+}
+
+fn in_progress(message: String) -> ObjectStatus {
+    ObjectStatus::InProgress(message)
+}
+
+struct ConditionRef<'a> {
+    status: &'a str,
+    reason: &'a str,
+    message: &'a str,
+}
+
+fn condition<'a>(status: &'a Value, type_: &str) -> Option<ConditionRef<'a>> {
+    status["conditions"]
+        .as_array()?
+        .iter()
+        .find(|c| c["type"] == type_)
+        .map(|c| ConditionRef {
+            status: c["status"].as_str().unwrap_or_default(),
+            reason: c["reason"].as_str().unwrap_or_default(),
+            message: c["message"].as_str().unwrap_or_default(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dynamic(value: Value) -> DynamicObject {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_object_status() {
+        let deployment = |updated: i64, available: i64| {
+            dynamic(json!({
+                "apiVersion": "apps/v1", "kind": "Deployment",
+                "metadata": {"name": "demo", "generation": 2},
+                "spec": {"replicas": 2},
+                "status": {"observedGeneration": 2, "replicas": 2, "updatedReplicas": updated, "availableReplicas": available}
+            }))
+        };
+        assert_eq!(
+            object_status("Deployment", &deployment(2, 2)),
+            ObjectStatus::Current
+        );
+        assert_eq!(
+            object_status("Deployment", &deployment(1, 1)),
+            ObjectStatus::InProgress("1/2 replicas updated".to_owned())
+        );
+        let stale = dynamic(json!({
+            "metadata": {"name": "demo", "generation": 3},
+            "status": {"observedGeneration": 2}
+        }));
+        assert!(matches!(
+            object_status("Deployment", &stale),
+            ObjectStatus::InProgress(_)
+        ));
+        let job = dynamic(json!({
+            "metadata": {"name": "migrate"},
+            "status": {"conditions": [{"type": "Failed", "status": "True", "message": "BackoffLimitExceeded"}]}
+        }));
+        assert_eq!(
+            object_status("Job", &job),
+            ObjectStatus::Failed("BackoffLimitExceeded".to_owned())
+        );
+        let custom = dynamic(json!({
+            "metadata": {"name": "cert"},
+            "status": {"conditions": [{"type": "Ready", "status": "False", "message": "issuing"}]}
+        }));
+        assert_eq!(
+            object_status("Certificate", &custom),
+            ObjectStatus::InProgress("issuing".to_owned())
+        );
+        let configmap = dynamic(json!({"metadata": {"name": "config"}, "data": {}}));
+        assert_eq!(
+            object_status("ConfigMap", &configmap),
+            ObjectStatus::Current
+        );
+    }
+
+    #[test]
+    fn test_manifest_objects() {
+        let manifest = r#"---
+# Source: demo/templates/service.yaml
+apiVersion: v1
+kind: Service
+metadata:
+  name: demo
+---
+apiVersion: rbac.authorization.k8s.io/v1
+kind: ClusterRole
+metadata:
+  name: demo
+  namespace: other
+---
+"#;
+        let objects = manifest_objects(manifest, "default").unwrap();
+        let described: Vec<_> = objects.iter().map(describe).collect();
+        assert_eq!(
+            described,
+            vec!["Service/default/demo", "ClusterRole/other/demo"]
+        );
+    }
+}
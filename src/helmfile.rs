@@ -1,155 +1,554 @@
 use crate::crd::Helmfile;
-use crate::util::NS;
+use crate::exec::{ExecError, Executor, Invocation, Output, Workspace};
+use crate::logs::{CommandLog, LogStore};
+use crate::metrics::{CommandLabels, HELMFILE_RUN_DURATION};
+use crate::redact::{redact, redact_manifests};
+use crate::util::{timestamp_now, truncate, truncate_start, NS};
 use async_trait::async_trait;
 use kube::ResourceExt;
-use std::str;
-use std::{path::Path, time::Duration};
-use tokio::{process::Command, time};
+use serde_derive::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+const MAX_LOG_SIZE: usize = 64 * 1024;
+const MAX_SUMMARY_SIZE: usize = 1024;
 
 #[derive(Debug)]
 pub enum HelmfileResult {
     Applied,
     NoChange,
     Failed(String),
+    /// the command was terminated by a controller shutdown
+    Interrupted,
+}
+
+#[derive(Debug)]
+pub enum DiffResult {
+    NoChange,
+    Changes(String),
+    Failed(String),
+    /// the command was terminated by a controller shutdown
+    Interrupted,
+}
+
+/// A helm release defined in a helmfile
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Release {
+    pub name: String,
+    pub namespace: String,
+    pub chart: String,
+    pub version: String,
+    /// current helm revision, None if the release is not installed
+    pub revision: Option<i64>,
+    /// helm status of the current revision
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListEntry {
+    name: String,
+    #[serde(default)]
+    namespace: String,
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    chart: String,
+    #[serde(default)]
+    version: String,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Deserialize)]
+struct HistoryEntry {
+    revision: i64,
+    status: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Apply,
     Sync,
 }
 
-pub struct HelmfileAdapterImpl {}
+#[derive(Clone)]
+pub struct HelmfileAdapterImpl {
+    executor: Arc<dyn Executor>,
+    logs: LogStore,
+    default_timeout: Duration,
+}
+
+impl HelmfileAdapterImpl {
+    pub fn new(executor: Arc<dyn Executor>, logs: LogStore, default_timeout: Duration) -> Self {
+        Self {
+            executor,
+            logs,
+            default_timeout,
+        }
+    }
+
+    pub fn logs(&self) -> &LogStore {
+        &self.logs
+    }
+
+    /// Releases what the executor kept for the commands of a reconcile or cleanup
+    pub async fn finish(&self, obj: &Helmfile) {
+        self.executor.finish(obj).await;
+    }
+
+    /// Runs a command and records its output in the log store
+    async fn execute(&self, invocation: Invocation, obj: &Helmfile) -> Result<Output, ExecError> {
+        let command = format!("{} {}", invocation.program, invocation.args.join(" "));
+        let manifests = invocation.manifests;
+        let labels = (invocation.program == "helmfile").then(|| CommandLabels {
+            namespace: obj.namespace().unwrap_or_else(|| NS.to_owned()),
+            name: obj.name_any(),
+            command: invocation.args.first().cloned().unwrap_or_default(),
+        });
+        let start = Instant::now();
+        let result = self
+            .executor
+            .execute(invocation, obj)
+            .instrument(tracing::info_span!("command", command))
+            .await;
+        if let Some(labels) = labels {
+            HELMFILE_RUN_DURATION
+                .get_or_create(&labels)
+                .observe(start.elapsed().as_secs_f64());
+        }
+        // rendered Secrets are masked before the output is stored or passed on
+        let result = result.map(|mut output| {
+            if manifests {
+                output.stdout = redact_manifests(&output.stdout);
+            }
+            output
+        });
+        let log = match &result {
+            Ok(output) => CommandLog {
+                time: timestamp_now(),
+                command,
+                exit_code: output.code,
+                stdout: truncate_start(&redact(&output.stdout), MAX_LOG_SIZE),
+                stderr: truncate_start(&redact(&output.stderr), MAX_LOG_SIZE),
+            },
+            Err(err) => CommandLog {
+                time: timestamp_now(),
+                command,
+                exit_code: None,
+                stderr: redact(&err.to_string()),
+                ..Default::default()
+            },
+        };
+        self.logs.record(obj, log).await;
+        result
+    }
+
+    /// Runs a command to completion and returns its stdout, or a summary of stderr on failure
+    async fn run(&self, invocation: Invocation, obj: &Helmfile) -> Result<String, ExecError> {
+        let output = self.execute(invocation, obj).await?;
+        if output.success() {
+            Ok(output.stdout)
+        } else {
+            Err(ExecError::Other(error_summary(&output.stderr)))
+        }
+    }
+}
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait HelmfileAdapter {
-    async fn apply(
+    async fn apply(&self, mode: Mode, workspace: &Workspace, obj: &Helmfile) -> HelmfileResult;
+    async fn diff(&self, workspace: &Workspace, obj: &Helmfile) -> DiffResult;
+    async fn template(&self, workspace: &Workspace, obj: &Helmfile) -> Result<String, ExecError>;
+    async fn destroy(&self, workspace: &Workspace, obj: &Helmfile) -> HelmfileResult;
+    /// Lists the enabled releases of the helmfile with their current helm revision
+    async fn releases(&self, workspace: &Workspace, obj: &Helmfile)
+        -> Result<Vec<Release>, String>;
+    async fn rollback(
         &self,
-        mode: Mode,
-        location: &Path,
+        release: &Release,
+        revision: i64,
         obj: &Helmfile,
-        extra_env: Option<(String, String)>,
-    ) -> HelmfileResult;
-    async fn destroy(
-        &self,
-        location: &Path,
-        obj: &Helmfile,
-        extra_env: Option<(String, String)>,
-    ) -> HelmfileResult;
+    ) -> Result<(), String>;
+    async fn uninstall(&self, release: &Release, obj: &Helmfile) -> Result<(), String>;
+    /// Returns the rendered manifest of the installed release
+    async fn manifest(&self, release: &Release, obj: &Helmfile) -> Result<String, String>;
 }
 
 #[async_trait]
 impl HelmfileAdapter for HelmfileAdapterImpl {
-    async fn apply(
-        &self,
-        mode: Mode,
-        location: &Path,
-        obj: &Helmfile,
-        extra_env: Option<(String, String)>,
-    ) -> HelmfileResult {
-        let mut cmd = Command::new("helmfile");
-        cmd.kill_on_drop(true); // make sure we can cancel the process if it takes too long
-        match mode {
-            Mode::Apply => cmd
-                .arg("apply")
-                .arg("--skip-diff-on-install")
-                .arg("--suppress-diff")
-                .arg("--detailed-exitcode"),
-            Mode::Sync => cmd.arg("sync"),
+    async fn apply(&self, mode: Mode, workspace: &Workspace, obj: &Helmfile) -> HelmfileResult {
+        let args: &[&str] = match mode {
+            Mode::Apply => &[
+                "apply",
+                "--skip-diff-on-install",
+                "--suppress-diff",
+                "--detailed-exitcode",
+            ],
+            Mode::Sync => &["sync"],
         };
-        if let Some(environment) = obj.spec.environment.as_ref() {
-            cmd.arg("-e").arg(environment);
-        }
-        if let Some(service_account) = obj.spec.service_account_name.as_ref() {
-            cmd.arg("--args").arg(format!(
-                "--kube-as-user=system:serviceaccount:{}:{service_account}",
-                obj.namespace().unwrap_or_else(|| NS.to_owned())
-            ));
-        }
-        cmd.current_dir(location);
-
-        if let Some(extra_env) = extra_env {
-            cmd.env(extra_env.0, extra_env.1);
-        }
-
-        let timeout = obj
-            .spec
-            .options
-            .as_ref()
-            .and_then(|o| o.timeout.as_ref())
-            .cloned()
-            .unwrap_or_else(|| "10m".to_owned());
-        let timeout = parse_duration::parse(&timeout).unwrap_or_else(|err| {
-            tracing::warn!("Could not parse duration: '{timeout}: {err}");
-            Duration::from_secs(10 * 60)
-        });
+        let invocation = helmfile_invocation(args, workspace, obj, self.default_timeout);
 
-        match time::timeout(timeout, cmd.output()).await {
-            Ok(Ok(output)) => match (output.status.code().unwrap_or(0), mode) {
+        match self.execute(invocation, obj).await {
+            Ok(output) => match (output.code.unwrap_or(0), mode) {
                 (2, _) => HelmfileResult::Applied,
                 (0, Mode::Apply) => HelmfileResult::NoChange,
                 (0, Mode::Sync) => HelmfileResult::Applied,
-                _ => HelmfileResult::Failed(
-                    str::from_utf8(&output.stderr)
-                        .unwrap_or("failed to read stderr")
-                        .to_owned(),
-                ),
+                _ => HelmfileResult::Failed(error_summary(&output.stderr)),
             },
-            Ok(Err(err)) => HelmfileResult::Failed(err.to_string()),
-            Err(_) => HelmfileResult::Failed("timeout".to_owned()),
+            Err(ExecError::Interrupted) => HelmfileResult::Interrupted,
+            Err(err) => HelmfileResult::Failed(err.to_string()),
         }
     }
 
-    async fn destroy(
-        &self,
-        location: &Path,
-        obj: &Helmfile,
-        extra_env: Option<(String, String)>,
-    ) -> HelmfileResult {
-        let mut cmd = Command::new("helmfile");
-        cmd.arg("destroy");
-        if let Some(environment) = obj.spec.environment.as_ref() {
-            cmd.arg("-e").arg(environment);
-        }
-        if let Some(service_account) = obj.spec.service_account_name.as_ref() {
-            cmd.arg("--args").arg(format!(
-                "--kube-as-user=system:serviceaccount:{}:{service_account}",
-                obj.namespace().unwrap_or_else(|| NS.to_owned())
-            ));
+    async fn diff(&self, workspace: &Workspace, obj: &Helmfile) -> DiffResult {
+        let invocation = helmfile_invocation(
+            &["diff", "--detailed-exitcode", "--suppress-secrets"],
+            workspace,
+            obj,
+            self.default_timeout,
+        );
+
+        match self.execute(invocation, obj).await {
+            Ok(output) => match output.code.unwrap_or(0) {
+                0 => DiffResult::NoChange,
+                2 => DiffResult::Changes(output.stdout),
+                _ => DiffResult::Failed(error_summary(&output.stderr)),
+            },
+            Err(ExecError::Interrupted) => DiffResult::Interrupted,
+            Err(err) => DiffResult::Failed(err.to_string()),
         }
-        cmd.current_dir(location);
+    }
+
+    async fn template(&self, workspace: &Workspace, obj: &Helmfile) -> Result<String, ExecError> {
+        let mut invocation =
+            helmfile_invocation(&["template"], workspace, obj, self.default_timeout);
+        invocation.manifests = true;
+        self.run(invocation, obj).await
+    }
 
-        if let Some(extra_env) = extra_env {
-            cmd.env(extra_env.0, extra_env.1);
+    async fn destroy(&self, workspace: &Workspace, obj: &Helmfile) -> HelmfileResult {
+        match self
+            .run(
+                helmfile_invocation(&["destroy"], workspace, obj, self.default_timeout),
+                obj,
+            )
+            .await
+        {
+            Ok(_) => HelmfileResult::Applied,
+            Err(ExecError::Interrupted) => HelmfileResult::Interrupted,
+            Err(err) => HelmfileResult::Failed(err.to_string()),
         }
+    }
 
-        let timeout = obj
-            .spec
-            .options
-            .as_ref()
-            .and_then(|o| o.timeout.as_ref())
-            .cloned()
-            .unwrap_or_else(|| "10m".to_owned());
-        let timeout = parse_duration::parse(&timeout).unwrap_or_else(|err| {
-            tracing::warn!("Could not parse duration: '{timeout}: {err}");
-            Duration::from_secs(10 * 60)
-        });
+    async fn releases(
+        &self,
+        workspace: &Workspace,
+        obj: &Helmfile,
+    ) -> Result<Vec<Release>, String> {
+        let invocation = helmfile_invocation(
+            &["list", "--output", "json"],
+            workspace,
+            obj,
+            self.default_timeout,
+        );
+        let output = self.run(invocation, obj).await.map_err(|e| e.to_string())?;
+        let mut releases = parse_releases(&output)?;
 
-        match time::timeout(timeout, cmd.output()).await {
-            Ok(Ok(output)) => {
-                if output.status.success() {
-                    HelmfileResult::Applied
-                } else {
-                    HelmfileResult::Failed(
-                        str::from_utf8(&output.stderr)
-                            .unwrap_or("failed to read stderr")
-                            .to_owned(),
-                    )
+        for release in releases.iter_mut() {
+            let invocation = helm_invocation(
+                release,
+                &["history", &release.name, "--max", "1", "--output", "json"],
+                obj,
+                self.default_timeout,
+            );
+            // a missing release is reported as an error by helm
+            if let Ok(output) = self.run(invocation, obj).await {
+                if let Some(entry) = parse_history(&output)? {
+                    release.revision = Some(entry.revision);
+                    release.status = Some(entry.status);
                 }
             }
-            Ok(Err(err)) => HelmfileResult::Failed(err.to_string()),
-            Err(_) => HelmfileResult::Failed("timeout".to_owned()),
         }
+        Ok(releases)
+    }
+
+    async fn rollback(
+        &self,
+        release: &Release,
+        revision: i64,
+        obj: &Helmfile,
+    ) -> Result<(), String> {
+        let revision = revision.to_string();
+        let invocation = helm_invocation(
+            release,
+            &["rollback", &release.name, &revision],
+            obj,
+            self.default_timeout,
+        );
+        self.run(invocation, obj)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn uninstall(&self, release: &Release, obj: &Helmfile) -> Result<(), String> {
+        let invocation = helm_invocation(
+            release,
+            &["uninstall", &release.name],
+            obj,
+            self.default_timeout,
+        );
+        self.run(invocation, obj)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn manifest(&self, release: &Release, obj: &Helmfile) -> Result<String, String> {
+        let mut invocation = helm_invocation(
+            release,
+            &["get", "manifest", &release.name],
+            obj,
+            self.default_timeout,
+        );
+        invocation.manifests = true;
+        self.run(invocation, obj).await.map_err(|e| e.to_string())
+    }
+}
+
+/// Builds a helm command for a release using the same identity as helmfile
+fn helm_invocation(
+    release: &Release,
+    args: &[&str],
+    obj: &Helmfile,
+    default_timeout: Duration,
+) -> Invocation {
+    let mut all_args = Vec::new();
+    if !release.namespace.is_empty() {
+        all_args.push("--namespace".to_owned());
+        all_args.push(release.namespace.clone());
+    }
+    if let Some(service_account) = obj.spec.service_account_name.as_ref() {
+        all_args.push(format!(
+            "--kube-as-user=system:serviceaccount:{}:{service_account}",
+            obj.namespace().unwrap_or_else(|| NS.to_owned())
+        ));
+    }
+    all_args.extend(args.iter().map(|a| a.to_string()));
+    Invocation {
+        program: "helm",
+        args: all_args,
+        workspace: None,
+        timeout: timeout(obj, default_timeout),
+        manifests: false,
+    }
+}
+
+/// Adds the arguments and workspace shared by all helmfile commands
+fn helmfile_invocation(
+    args: &[&str],
+    workspace: &Workspace,
+    obj: &Helmfile,
+    default_timeout: Duration,
+) -> Invocation {
+    let mut all_args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    if let Some(environment) = obj.spec.environment.as_ref() {
+        all_args.push("-e".to_owned());
+        all_args.push(environment.clone());
+    }
+    if let Some(service_account) = obj.spec.service_account_name.as_ref() {
+        all_args.push("--args".to_owned());
+        all_args.push(format!(
+            "--kube-as-user=system:serviceaccount:{}:{service_account}",
+            obj.namespace().unwrap_or_else(|| NS.to_owned())
+        ));
+    }
+    Invocation {
+        program: "helmfile",
+        args: all_args,
+        workspace: Some(workspace.clone()),
+        timeout: timeout(obj, default_timeout),
+        manifests: false,
+    }
+}
+
+fn parse_releases(output: &str) -> Result<Vec<Release>, String> {
+    let entries: Vec<ListEntry> = serde_json::from_str(output)
+        .map_err(|e| format!("Could not parse output of helmfile list: {e}"))?;
+    Ok(entries
+        .into_iter()
+        .filter(|e| e.enabled)
+        .map(|e| Release {
+            name: e.name,
+            namespace: e.namespace,
+            chart: e.chart,
+            version: e.version,
+            ..Default::default()
+        })
+        .collect())
+}
+
+fn parse_history(output: &str) -> Result<Option<HistoryEntry>, String> {
+    let entries: Vec<HistoryEntry> = serde_json::from_str(output)
+        .map_err(|e| format!("Could not parse output of helm history: {e}"))?;
+    Ok(entries.into_iter().max_by_key(|e| e.revision))
+}
+
+/// Extracts a short error message from the stderr of a failed command, the full
+/// output is available in the log store
+fn error_summary(stderr: &str) -> String {
+    let stderr = redact(stderr);
+    let lines: Vec<_> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let summary = lines
+        .iter()
+        .rev()
+        .find(|l| l.starts_with("Error") || l.starts_with("err:") || l.contains("error:"))
+        .or(lines.last())
+        .copied()
+        .unwrap_or("command failed without output");
+    truncate(summary, MAX_SUMMARY_SIZE)
+}
+
+fn timeout(obj: &Helmfile, default_timeout: Duration) -> Duration {
+    let Some(timeout) = obj.spec.options.as_ref().and_then(|o| o.timeout.as_ref()) else {
+        return default_timeout;
+    };
+    parse_duration::parse(timeout).unwrap_or_else(|err| {
+        tracing::warn!("Could not parse duration: '{timeout}: {err}");
+        default_timeout
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::INTERRUPTED;
+    use kube::api::ObjectMeta;
+
+    /// Executor that prints a rendered Secret on stdout
+    struct RenderExecutor;
+
+    #[async_trait]
+    impl Executor for RenderExecutor {
+        async fn execute(
+            &self,
+            _invocation: Invocation,
+            _obj: &Helmfile,
+        ) -> Result<Output, ExecError> {
+            Ok(Output {
+                code: Some(0),
+                stdout: "apiVersion: v1\nkind: Secret\nmetadata:\n  name: demo\ndata:\n  password: czNjcjN0\nstringData:\n  token: s3cr3t-t0ken\n".to_owned(),
+                stderr: String::new(),
+            })
+        }
+    }
+
+    /// Executor whose commands all fail with the same error
+    struct FailingExecutor(ExecError);
+
+    #[async_trait]
+    impl Executor for FailingExecutor {
+        async fn execute(
+            &self,
+            _invocation: Invocation,
+            _obj: &Helmfile,
+        ) -> Result<Output, ExecError> {
+            Err(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_interrupted() {
+        let obj = Helmfile::default();
+        let adapter = |error| {
+            HelmfileAdapterImpl::new(
+                Arc::new(FailingExecutor(error)),
+                LogStore::new(0),
+                Duration::from_secs(60),
+            )
+        };
+        let result = adapter(ExecError::Interrupted)
+            .apply(Mode::Apply, &Workspace::default(), &obj)
+            .await;
+        assert!(matches!(result, HelmfileResult::Interrupted));
+        // only the executor decides about an interruption, not the text of an error
+        let result = adapter(ExecError::Other(INTERRUPTED.to_owned()))
+            .diff(&Workspace::default(), &obj)
+            .await;
+        assert!(matches!(result, DiffResult::Failed(reason) if reason == INTERRUPTED));
+    }
+
+    #[tokio::test]
+    async fn test_template_masks_secrets() {
+        let logs = LogStore::new(10);
+        let adapter = HelmfileAdapterImpl::new(
+            Arc::new(RenderExecutor),
+            logs.clone(),
+            Duration::from_secs(60),
+        );
+        let obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("bar".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let rendered = adapter.template(&Workspace::default(), &obj).await.unwrap();
+        assert!(rendered.contains("name: demo"));
+        assert!(!rendered.contains("czNjcjN0"));
+        assert!(!rendered.contains("s3cr3t-t0ken"));
+        let release = Release {
+            name: "demo".to_owned(),
+            ..Default::default()
+        };
+        let manifest = adapter.manifest(&release, &obj).await.unwrap();
+        assert!(!manifest.contains("czNjcjN0"));
+
+        let logs = logs.get(&(&obj).into()).await.unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs.iter().all(|log| !log.stdout.contains("czNjcjN0")));
+    }
+
+    #[test]
+    fn test_parse_releases() {
+        let output = r#"[{"name":"demo","namespace":"default","enabled":true,"installed":true,"labels":"","chart":"./demo","version":""},
+            {"name":"disabled","namespace":"","enabled":false,"installed":true,"labels":"","chart":"bitnami/nginx","version":"1.0.0"}]"#;
+        let releases = parse_releases(output).unwrap();
+        assert_eq!(
+            releases,
+            vec![Release {
+                name: "demo".to_owned(),
+                namespace: "default".to_owned(),
+                chart: "./demo".to_owned(),
+                ..Default::default()
+            }]
+        );
+        assert!(parse_releases("no json").is_err());
+    }
+
+    #[test]
+    fn test_error_summary() {
+        let stderr = "Adding repo bitnami https://charts.bitnami.com/bitnami\n\nUPDATED RELEASES:\nin ./helmfile.yaml: failed processing release demo: command \"helm\" exited with non-zero status:\n\nERROR:\n  exit status 1\n\nEXIT STATUS\n  1\n\nSTDERR:\n  Error: UPGRADE FAILED: timed out waiting for the condition\n\nCOMBINED OUTPUT:\n  done\n";
+        assert_eq!(
+            error_summary(stderr),
+            "Error: UPGRADE FAILED: timed out waiting for the condition"
+        );
+        assert_eq!(error_summary("something broke\n"), "something broke");
+        assert_eq!(error_summary(""), "command failed without output");
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let output = r#"[{"revision":2,"updated":"2024-01-03T10:49:45.000000000+01:00","status":"superseded","chart":"demo-0.1.0","app_version":"1.0.0","description":"Upgrade complete"},
+            {"revision":3,"updated":"2024-01-04T10:49:45.000000000+01:00","status":"failed","chart":"demo-0.1.1","app_version":"1.0.0","description":"Upgrade failed"}]"#;
+        let entry = parse_history(output).unwrap().unwrap();
+        assert_eq!(entry.revision, 3);
+        assert_eq!(entry.status, "failed");
+        assert!(parse_history("[]").unwrap().is_none());
     }
 }
@@ -1,8 +1,11 @@
+use crate::crd::{HealthCheckRef, Helmfile};
 use async_trait::async_trait;
-use k8s_openapi::api::core::v1::Secret;
-use kube::api::{Patch, PatchParams};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::api::{DynamicObject, GroupVersionKind, Patch, PatchParams};
 use kube::client::Client;
-use kube::Api;
+use kube::discovery::{self, Scope};
+use kube::runtime::events::{Event, Recorder, Reporter};
+use kube::{Api, Resource};
 use serde_json::Value;
 
 const PATCH_OWNER: &str = "flux-helmfile-controller";
@@ -22,6 +25,16 @@ pub trait K8sClient: Clone {
         name: &str,
         patch: &Patch<Value>,
     ) -> Result<(), kube::error::Error>;
+    async fn apply_configmap(
+        &self,
+        namespace: &str,
+        configmap: &ConfigMap,
+    ) -> Result<(), kube::error::Error>;
+    async fn get_object(
+        &self,
+        object: &HealthCheckRef,
+    ) -> Result<Option<DynamicObject>, kube::error::Error>;
+    async fn publish_event(&self, obj: &Helmfile, event: Event) -> Result<(), kube::error::Error>;
 }
 
 #[derive(Clone)]
@@ -47,7 +60,7 @@ impl K8sClient for K8sClientImpl {
         name: &str,
         patch: &Patch<Value>,
     ) -> Result<(), kube::error::Error> {
-        let api = Api::<Secret>::namespaced(self.client.clone(), namespace);
+        let api = Api::<Helmfile>::namespaced(self.client.clone(), namespace);
         let ps = PatchParams::apply(PATCH_OWNER);
         api.patch_metadata(name, &ps, patch).await?;
         Ok(())
@@ -58,11 +71,48 @@ impl K8sClient for K8sClientImpl {
         name: &str,
         patch: &Patch<Value>,
     ) -> Result<(), kube::error::Error> {
-        let api = Api::<Secret>::namespaced(self.client.clone(), namespace);
+        let api = Api::<Helmfile>::namespaced(self.client.clone(), namespace);
         let ps = PatchParams::apply(PATCH_OWNER).force();
         api.patch_status(name, &ps, patch).await?;
         Ok(())
     }
+    async fn apply_configmap(
+        &self,
+        namespace: &str,
+        configmap: &ConfigMap,
+    ) -> Result<(), kube::error::Error> {
+        let api = Api::<ConfigMap>::namespaced(self.client.clone(), namespace);
+        let ps = PatchParams::apply(PATCH_OWNER).force();
+        let name = configmap.metadata.name.clone().unwrap_or_default();
+        api.patch(&name, &ps, &Patch::Apply(configmap)).await?;
+        Ok(())
+    }
+    async fn get_object(
+        &self,
+        object: &HealthCheckRef,
+    ) -> Result<Option<DynamicObject>, kube::error::Error> {
+        let (group, version) = object
+            .api_version
+            .split_once('/')
+            .unwrap_or(("", &object.api_version));
+        let gvk = GroupVersionKind::gvk(group, version, &object.kind);
+        let (resource, capabilities) = discovery::pinned_kind(&self.client, &gvk).await?;
+        let api = match (capabilities.scope, object.namespace.as_ref()) {
+            (Scope::Namespaced, Some(namespace)) => {
+                Api::<DynamicObject>::namespaced_with(self.client.clone(), namespace, &resource)
+            }
+            _ => Api::<DynamicObject>::all_with(self.client.clone(), &resource),
+        };
+        api.get_opt(&object.name).await
+    }
+    async fn publish_event(&self, obj: &Helmfile, event: Event) -> Result<(), kube::error::Error> {
+        let reporter = Reporter {
+            controller: PATCH_OWNER.to_owned(),
+            instance: std::env::var("POD_NAME").ok(),
+        };
+        let recorder = Recorder::new(self.client.clone(), reporter, obj.object_ref(&()));
+        recorder.publish(event).await
+    }
 }
 
 #[cfg(test)]
@@ -77,6 +127,9 @@ pub mod tests {
             async fn get_secret(&self, namespace: &str, name: &str) -> Result<Secret, kube::error::Error>;
             async fn patch_helmfile_metadata(&self, namespace: &str, name: &str, patch: &Patch<Value>) -> Result<(), kube::error::Error>;
             async fn patch_helmfile_status(&self, namespace: &str, name: &str, patch: &Patch<Value>) -> Result<(), kube::error::Error>;
+            async fn apply_configmap(&self, namespace: &str, configmap: &ConfigMap) -> Result<(), kube::error::Error>;
+            async fn get_object(&self, object: &HealthCheckRef) -> Result<Option<DynamicObject>, kube::error::Error>;
+            async fn publish_event(&self, obj: &Helmfile, event: Event) -> Result<(), kube::error::Error>;
         }
         impl Clone for Client {
             fn clone(&self) -> Self;
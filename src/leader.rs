@@ -0,0 +1,217 @@
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+use kube::api::PostParams;
+use kube::core::ObjectMeta;
+use kube::{Api, Client};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+const LEASE_DURATION_SECONDS: i32 = 15;
+/// the leader gives up if it could not renew the lease for this long, well before the lease expires
+const RENEW_DEADLINE: Duration = Duration::from_secs(10);
+const RETRY_PERIOD: Duration = Duration::from_secs(2);
+
+/// Elects a single leader between the controller replicas with a coordination.k8s.io Lease
+#[derive(Clone)]
+pub struct LeaderElection {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+}
+
+impl LeaderElection {
+    pub fn new(client: Client, namespace: &str, name: String, identity: String) -> Self {
+        Self {
+            api: Api::namespaced(client, namespace),
+            name,
+            identity,
+        }
+    }
+
+    /// Waits until this replica holds the lease
+    pub async fn acquire(&self) {
+        let mut waiting = false;
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => {
+                    tracing::info!("Acquired leadership as {}", self.identity);
+                    return;
+                }
+                Ok(false) if !waiting => {
+                    tracing::info!("Another replica is the leader, waiting as standby");
+                    waiting = true;
+                }
+                Ok(false) => (),
+                Err(err) => tracing::warn!("Could not acquire leader lease: {err}"),
+            }
+            tokio::time::sleep(RETRY_PERIOD).await;
+        }
+    }
+
+    /// Renews the lease in the background until the task is aborted.
+    /// The receiver resolves once the lease was taken over or could not be renewed in time.
+    pub fn renew(&self) -> (JoinHandle<()>, oneshot::Receiver<()>) {
+        let (lost, receiver) = oneshot::channel();
+        let election = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut renewed = Instant::now();
+            loop {
+                tokio::time::sleep(RETRY_PERIOD).await;
+                match election.try_acquire_or_renew().await {
+                    Ok(true) => renewed = Instant::now(),
+                    Ok(false) => {
+                        tracing::warn!("Leader lease was taken over by another replica");
+                        break;
+                    }
+                    Err(err) if renewed.elapsed() > RENEW_DEADLINE => {
+                        tracing::warn!("Could not renew leader lease in time: {err}");
+                        break;
+                    }
+                    Err(err) => tracing::debug!("Could not renew leader lease, retrying: {err}"),
+                }
+            }
+            let _ = lost.send(());
+        });
+        (handle, receiver)
+    }
+
+    /// Clears the holder of the lease so a standby can take over right away
+    pub async fn release(&self) {
+        match self.clear_holder().await {
+            Ok(true) => tracing::info!("Released leadership"),
+            Ok(false) => (),
+            Err(err) => tracing::warn!("Could not release leader lease: {err}"),
+        }
+    }
+
+    async fn clear_holder(&self) -> Result<bool> {
+        let Some(mut lease) = self.api.get_opt(&self.name).await? else {
+            return Ok(false);
+        };
+        let Some(spec) = lease
+            .spec
+            .as_mut()
+            .filter(|s| s.holder_identity.as_deref() == Some(self.identity.as_str()))
+        else {
+            return Ok(false);
+        };
+        spec.holder_identity = None;
+        spec.lease_duration_seconds = Some(1);
+        self.api
+            .replace(&self.name, &PostParams::default(), &lease)
+            .await?;
+        Ok(true)
+    }
+
+    /// Takes or renews the lease, returns false if another replica holds it.
+    /// Concurrent updates are rejected by the resourceVersion of the lease.
+    async fn try_acquire_or_renew(&self) -> Result<bool> {
+        let lease = self.api.get_opt(&self.name).await?;
+        let current = lease.as_ref().and_then(|l| l.spec.as_ref());
+        let Some(spec) = next_spec(current, lease.is_some(), &self.identity, Utc::now()) else {
+            return Ok(false);
+        };
+        let result = match lease {
+            Some(mut lease) => {
+                lease.spec = Some(spec);
+                self.api
+                    .replace(&self.name, &PostParams::default(), &lease)
+                    .await
+            }
+            None => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(self.name.clone()),
+                        ..Default::default()
+                    },
+                    spec: Some(spec),
+                };
+                self.api.create(&PostParams::default(), &lease).await
+            }
+        };
+        match result {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(err)) if err.code == 409 => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Returns the spec to write to take or keep the lease, None if another holder's lease has not expired
+fn next_spec(
+    current: Option<&LeaseSpec>,
+    exists: bool,
+    identity: &str,
+    now: DateTime<Utc>,
+) -> Option<LeaseSpec> {
+    let current = current.cloned().unwrap_or_default();
+    let renewed = LeaseSpec {
+        holder_identity: Some(identity.to_owned()),
+        lease_duration_seconds: Some(LEASE_DURATION_SECONDS),
+        renew_time: Some(MicroTime(now)),
+        ..current.clone()
+    };
+    match current.holder_identity.as_deref() {
+        Some(holder) if holder == identity => Some(renewed),
+        Some(holder) if !holder.is_empty() && !expired(&current, now) => None,
+        _ => Some(LeaseSpec {
+            acquire_time: Some(MicroTime(now)),
+            lease_transitions: Some(match exists {
+                true => current.lease_transitions.unwrap_or_default() + 1,
+                false => 0,
+            }),
+            ..renewed
+        }),
+    }
+}
+
+fn expired(spec: &LeaseSpec, now: DateTime<Utc>) -> bool {
+    let Some(MicroTime(renewed)) = spec.renew_time.as_ref().or(spec.acquire_time.as_ref()) else {
+        return true;
+    };
+    let duration = spec
+        .lease_duration_seconds
+        .unwrap_or(LEASE_DURATION_SECONDS);
+    *renewed + chrono::Duration::seconds(duration.into()) < now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_spec() {
+        let start = Utc::now();
+        let acquired = next_spec(None, false, "replica-a", start).unwrap();
+        assert_eq!(acquired.holder_identity.as_deref(), Some("replica-a"));
+        assert_eq!(acquired.acquire_time, Some(MicroTime(start)));
+        assert_eq!(acquired.lease_transitions, Some(0));
+
+        // the holder renews without changing the acquire time
+        let later = start + chrono::Duration::seconds(5);
+        let renewed = next_spec(Some(&acquired), true, "replica-a", later).unwrap();
+        assert_eq!(renewed.acquire_time, Some(MicroTime(start)));
+        assert_eq!(renewed.renew_time, Some(MicroTime(later)));
+        assert_eq!(renewed.lease_transitions, Some(0));
+
+        // a standby waits while the lease is valid and takes over once it expired
+        assert_eq!(next_spec(Some(&renewed), true, "replica-b", later), None);
+        let expired = later + chrono::Duration::seconds(LEASE_DURATION_SECONDS as i64 + 1);
+        let taken = next_spec(Some(&renewed), true, "replica-b", expired).unwrap();
+        assert_eq!(taken.holder_identity.as_deref(), Some("replica-b"));
+        assert_eq!(taken.acquire_time, Some(MicroTime(expired)));
+        assert_eq!(taken.lease_transitions, Some(1));
+
+        // a released lease can be taken right away
+        let released = LeaseSpec {
+            holder_identity: None,
+            ..taken
+        };
+        let taken = next_spec(Some(&released), true, "replica-a", expired).unwrap();
+        assert_eq!(taken.holder_identity.as_deref(), Some("replica-a"));
+        assert_eq!(taken.lease_transitions, Some(2));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::crd::Helmfile;
+use crate::store::NamespacedName;
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Output of a single helmfile or helm command
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandLog {
+    pub time: String,
+    pub command: String,
+    /// exit code, None if the command could not be run, timed out or was killed
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Ring buffer with the output of the last commands run for each Helmfile
+#[derive(Clone)]
+pub struct LogStore {
+    max_entries: usize,
+    entries: Arc<RwLock<HashMap<NamespacedName, VecDeque<CommandLog>>>>,
+}
+
+impl LogStore {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn record(&self, obj: &Helmfile, log: CommandLog) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.write().await;
+        let logs = entries.entry(obj.into()).or_default();
+        while logs.len() >= self.max_entries {
+            logs.pop_front();
+        }
+        logs.push_back(log);
+    }
+
+    /// Returns the logs of a Helmfile, oldest first
+    pub async fn get(&self, key: &NamespacedName) -> Option<Vec<CommandLog>> {
+        let entries = self.entries.read().await;
+        entries.get(key).map(|logs| logs.iter().cloned().collect())
+    }
+
+    pub async fn remove(&self, key: &NamespacedName) {
+        self.entries.write().await.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::ObjectMeta;
+
+    #[tokio::test]
+    async fn test_log_store() {
+        let store = LogStore::new(2);
+        let obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("bar".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for command in ["first", "second", "third"] {
+            let log = CommandLog {
+                command: command.to_owned(),
+                ..Default::default()
+            };
+            store.record(&obj, log).await;
+        }
+        let key: NamespacedName = (&obj).into();
+        let commands: Vec<_> = store
+            .get(&key)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.command)
+            .collect();
+        assert_eq!(commands, vec!["second", "third"]);
+        store.remove(&key).await;
+        assert!(store.get(&key).await.is_none());
+    }
+}
@@ -1,40 +1,149 @@
 mod api;
+mod config;
 mod controller;
 mod crd;
 mod error;
+mod exec;
 mod extcrds;
 mod flux;
+mod health;
 mod helmfile;
 mod k8sclient;
+mod leader;
+mod logs;
 mod metrics;
+mod preflight;
 mod reconciler;
+mod redact;
+mod shutdown;
 mod store;
 mod util;
+mod webhooks;
 
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::{trace, Resource};
+use std::sync::Arc;
 use tracing_subscriber::{prelude::*, EnvFilter};
 
+const DEFAULT_LOG_HISTORY: usize = 20;
+const DEFAULT_LEADER_ELECTION_NAMESPACE: &str = "flux-system";
+const DEFAULT_LEADER_ELECTION_ID: &str = "helmfile-controller-leader";
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
-    init_logging();
+    let config = config::Config::load().expect("Invalid configuration");
+    init_logging(config.log_format.unwrap_or_default());
+    redact::set_patterns(config.redact_pattern.clone());
     metrics::init_metrics().await;
     let client = kube::Client::try_default()
         .await
         .expect("Could not initialize kube client");
+    let flux_adapter = flux::artifact::FluxSourceAdapterImpl::new(&config)
+        .expect("Could not initialize source-controller client");
+    let events = config.events_addr.clone().map(|address| {
+        flux::events::FluxEventForwarder::new(address, std::env::var("POD_NAME").ok())
+            .expect("Could not initialize event forwarding")
+    });
+    let webhooks = config
+        .webhook_config
+        .as_ref()
+        .map(|path| webhooks::WebhookNotifier::load(path).expect("Could not initialize webhooks"));
+    let election = config.leader_election.then(|| {
+        let namespace = config
+            .leader_election_namespace
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LEADER_ELECTION_NAMESPACE.to_owned());
+        let identity = std::env::var("POD_NAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_else(|_| format!("helmfile-controller-{}", std::process::id()));
+        let name = config
+            .leader_election_id
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LEADER_ELECTION_ID.to_owned());
+        leader::LeaderElection::new(client.clone(), &namespace, name, identity)
+    });
+    let shutdown = shutdown::Shutdown::new(config.shutdown_timeout());
+    shutdown.start_on_signal();
+    let readiness = api::Readiness::default();
+    let backend = config.execution_backend.unwrap_or_default();
+    if backend == config::ExecutionBackend::Local {
+        if let Err(problem) = preflight::run().await {
+            tracing::error!("Preflight failed, reconciles will fail: {problem}");
+            readiness.set("preflight", Some(problem));
+        }
+    } else {
+        tracing::info!(
+            "Skipping preflight, helmfile runs in jobs with the image {:?}",
+            config.job_image
+        );
+    }
+    let executor: Arc<dyn exec::Executor> = match backend {
+        config::ExecutionBackend::Local => Arc::new(exec::local::LocalExecutor {
+            cache_dir: config.cache_dir.clone(),
+            terminate: Some(shutdown.terminate()),
+        }),
+        config::ExecutionBackend::Job => Arc::new(
+            exec::job::JobExecutor::new(client.clone(), &config, Some(shutdown.terminate()))
+                .expect("Could not initialize job execution backend"),
+        ),
+    };
+    let logs = logs::LogStore::new(config.log_history.unwrap_or(DEFAULT_LOG_HISTORY));
+    let helmfile_adapter =
+        helmfile::HelmfileAdapterImpl::new(executor, logs.clone(), config.default_timeout());
     let store = store::new_store();
-    let handle = tokio::spawn(api::server());
-    controller::run(client, store).await;
+    let liveness = api::Liveness::default();
+    let handle = tokio::spawn(api::server(
+        config.bind_address(),
+        logs,
+        readiness.clone(),
+        liveness.clone(),
+    ));
+    let context = controller::Context {
+        client,
+        store,
+        config,
+        flux_adapter,
+        helmfile_adapter,
+        events,
+        webhooks,
+        liveness,
+        selected_namespaces: None,
+    };
+    let Some(election) = election else {
+        controller::run(context, readiness, shutdown.started()).await;
+        handle.abort();
+        opentelemetry::global::shutdown_tracer_provider();
+        return;
+    };
+    // standbys only serve the api and stay ready until they become the leader
+    tokio::select! {
+        _ = election.acquire() => (),
+        _ = shutdown.started() => {
+            handle.abort();
+            opentelemetry::global::shutdown_tracer_provider();
+            return;
+        }
+    }
+    let (renewal, lost) = election.renew();
+    shutdown.abort_on_lost_lease(lost);
+    controller::run(context, readiness, shutdown.started()).await;
+    // the lease is renewed until the running helmfile processes finished, then handed over
+    renewal.abort();
+    election.release().await;
     handle.abort();
+    opentelemetry::global::shutdown_tracer_provider();
 }
 
-fn init_logging() {
+fn init_logging(format: config::LogFormat) {
     let filter = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new("info"))
         .expect("Could not init logging");
 
-    let subscriber = tracing_subscriber::registry().with(filter);
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(init_tracing());
 
-    let log_mode = std::env::var("LOGGING_MODE").unwrap_or_else(|_| "plain".to_string());
-    if log_mode.to_lowercase() == "json" {
+    if format == config::LogFormat::Json {
         subscriber
             .with(tracing_subscriber::fmt::layer().json())
             .init();
@@ -42,3 +151,28 @@ fn init_logging() {
         subscriber.with(tracing_subscriber::fmt::layer()).init();
     }
 }
+
+/// Exports spans via OTLP/HTTP if an endpoint is configured with the standard
+/// OTEL_EXPORTER_OTLP_ENDPOINT or OTEL_EXPORTER_OTLP_TRACES_ENDPOINT variables
+fn init_tracing<S>() -> Option<tracing_opentelemetry::OpenTelemetryLayer<S, trace::Tracer>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err()
+        && std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_err()
+    {
+        return None;
+    }
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().http())
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "flux-helmfile-controller",
+            )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .expect("Could not init OTLP exporter");
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
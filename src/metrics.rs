@@ -3,11 +3,19 @@ use kube::ResourceExt;
 use lazy_static::lazy_static;
 use prometheus_client::{
     encoding::{text::encode, EncodeLabelSet},
-    metrics::{counter::Counter, family::Family},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
     registry::Registry,
 };
+use std::sync::atomic::AtomicU64;
 use tokio::sync::Mutex;
 
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
 lazy_static! {
     static ref REGISTRY: Mutex<Registry> = Mutex::new(<Registry>::default());
     pub static ref NUM_RECONCILES_STARTED: Family<HelmfileLabels, Counter> =
@@ -20,6 +28,30 @@ lazy_static! {
         Family::<HelmfileLabels, Counter>::default();
     pub static ref NUM_CLEANUPS_FAILED: Family<HelmfileLabels, Counter> =
         Family::<HelmfileLabels, Counter>::default();
+    pub static ref NUM_DRIFTS_DETECTED: Family<HelmfileLabels, Counter> =
+        Family::<HelmfileLabels, Counter>::default();
+    pub static ref DRIFTED: Family<HelmfileLabels, Gauge> =
+        Family::<HelmfileLabels, Gauge>::default();
+    pub static ref RECONCILE_DURATION: HistogramFamily<HelmfileLabels> =
+        HistogramFamily::new_with_constructor(duration_histogram);
+    pub static ref HELMFILE_RUN_DURATION: HistogramFamily<CommandLabels> =
+        HistogramFamily::new_with_constructor(duration_histogram);
+    pub static ref ARTIFACT_FETCH_DURATION: HistogramFamily<HelmfileLabels> =
+        HistogramFamily::new_with_constructor(duration_histogram);
+    pub static ref READY: Family<HelmfileLabels, Gauge> =
+        Family::<HelmfileLabels, Gauge>::default();
+    pub static ref FAILED: Family<HelmfileLabels, Gauge> =
+        Family::<HelmfileLabels, Gauge>::default();
+    pub static ref SUSPENDED: Family<HelmfileLabels, Gauge> =
+        Family::<HelmfileLabels, Gauge>::default();
+    pub static ref RETRIES: Family<HelmfileLabels, Gauge> =
+        Family::<HelmfileLabels, Gauge>::default();
+    pub static ref LAST_SUCCESSFUL_APPLY: Family<HelmfileLabels, Gauge<f64, AtomicU64>> =
+        Family::<HelmfileLabels, Gauge<f64, AtomicU64>>::default();
+    pub static ref BUILD_INFO: Family<BuildLabels, Gauge> = Family::<BuildLabels, Gauge>::default();
+    pub static ref TOOL_INFO: Family<ToolLabels, Gauge> = Family::<ToolLabels, Gauge>::default();
+    pub static ref APPLIED_REVISION: Family<RevisionLabels, Gauge> =
+        Family::<RevisionLabels, Gauge>::default();
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
@@ -28,6 +60,56 @@ pub struct HelmfileLabels {
     pub name: String,
 }
 
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct CommandLabels {
+    pub namespace: String,
+    pub name: String,
+    pub command: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct RevisionLabels {
+    pub namespace: String,
+    pub name: String,
+    pub revision: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct BuildLabels {
+    pub version: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+pub struct ToolLabels {
+    pub tool: String,
+    pub version: String,
+}
+
+/// Buckets from 0.5s to about 34m for the durations of reconciles and helmfile runs
+fn duration_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.5, 2.0, 13))
+}
+
+/// Removes all state metrics of a Helmfile, e.g. after it was deleted
+pub fn remove_state(obj: &Helmfile) {
+    let labels = l(obj);
+    for family in [&*DRIFTED, &*READY, &*FAILED, &*SUSPENDED, &*RETRIES] {
+        family.remove(&labels);
+    }
+    LAST_SUCCESSFUL_APPLY.remove(&labels);
+    if let Some(revision) = obj
+        .status
+        .as_ref()
+        .and_then(|s| s.last_applied_revision.clone())
+    {
+        APPLIED_REVISION.remove(&RevisionLabels {
+            namespace: labels.namespace,
+            name: labels.name,
+            revision,
+        });
+    }
+}
+
 pub fn l(obj: &Helmfile) -> HelmfileLabels {
     HelmfileLabels {
         namespace: obj.namespace().unwrap_or_else(|| "default".to_owned()),
@@ -45,7 +127,7 @@ pub async fn init_metrics() {
     );
     registry.register(
         format!("{base}_reconciles_pending_count"),
-        "Number of reconciles started",
+        "Number of reconciles that were pending, e.g. because the source was not ready",
         NUM_RECONCILES_PENDING.clone(),
     );
     registry.register(
@@ -63,6 +145,76 @@ pub async fn init_metrics() {
         "Number of cleanups failed",
         NUM_CLEANUPS_FAILED.clone(),
     );
+    registry.register(
+        format!("{base}_drifts_detected_count"),
+        "Number of times drift was detected",
+        NUM_DRIFTS_DETECTED.clone(),
+    );
+    registry.register(
+        format!("{base}_drifted"),
+        "Whether the last drift detection found drift (1) or not (0)",
+        DRIFTED.clone(),
+    );
+    registry.register(
+        format!("{base}_reconcile_duration_seconds"),
+        "Duration of reconciles",
+        RECONCILE_DURATION.clone(),
+    );
+    registry.register(
+        format!("{base}_helmfile_run_duration_seconds"),
+        "Duration of helmfile commands",
+        HELMFILE_RUN_DURATION.clone(),
+    );
+    registry.register(
+        format!("{base}_artifact_fetch_duration_seconds"),
+        "Duration of downloading and extracting artifacts",
+        ARTIFACT_FETCH_DURATION.clone(),
+    );
+    registry.register(
+        format!("{base}_ready"),
+        "Whether the last run of the helmfile succeeded (1) or not (0)",
+        READY.clone(),
+    );
+    registry.register(
+        format!("{base}_failed"),
+        "Whether the last run of the helmfile failed (1) or not (0)",
+        FAILED.clone(),
+    );
+    registry.register(
+        format!("{base}_suspended"),
+        "Whether the source of the helmfile is suspended (1) or not (0)",
+        SUSPENDED.clone(),
+    );
+    registry.register(
+        format!("{base}_retries"),
+        "Number of retries used since the last successful run",
+        RETRIES.clone(),
+    );
+    registry.register(
+        format!("{base}_last_successful_apply_timestamp_seconds"),
+        "Unix timestamp of the last successful run of the helmfile",
+        LAST_SUCCESSFUL_APPLY.clone(),
+    );
+    registry.register(
+        format!("{base}_applied_revision_info"),
+        "Revision of the source that was last applied",
+        APPLIED_REVISION.clone(),
+    );
+    BUILD_INFO
+        .get_or_create(&BuildLabels {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        })
+        .set(1);
+    registry.register(
+        format!("{base}_build_info"),
+        "Version of the controller",
+        BUILD_INFO.clone(),
+    );
+    registry.register(
+        format!("{base}_tool_info"),
+        "Versions of helmfile, helm, sops and the helm plugins found at startup, missing tools have version missing",
+        TOOL_INFO.clone(),
+    );
 }
 
 pub async fn metrics() -> Result<String, std::fmt::Error> {
@@ -0,0 +1,183 @@
+use crate::metrics::{ToolLabels, TOOL_INFO};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::Duration;
+use tokio::{process::Command, time};
+
+const TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+/// helmfile apply and diff need the helm-diff plugin
+const REQUIRED_PLUGINS: [&str; 1] = ["diff"];
+
+lazy_static! {
+    static ref VERSION: Regex = Regex::new(r"v?\d+\.\d+\.\d+[0-9A-Za-z.+-]*").unwrap();
+}
+
+/// A tool or helm plugin found (or not) by the preflight
+#[derive(Debug, PartialEq)]
+struct Tool {
+    name: String,
+    version: Option<String>,
+    required: bool,
+}
+
+/// Detects helmfile, helm, sops and the helm plugins, logs them and exports them as metrics.
+/// Returns a description of the problem if a required tool is missing.
+pub async fn run() -> Result<(), String> {
+    let mut tools = vec![
+        tool("helmfile", &["--version"], true).await,
+        tool("helm", &["version", "--short"], true).await,
+        tool("sops", &["--version"], false).await,
+    ];
+    let plugins = match output("helm", &["plugin", "list"]).await {
+        Some(output) => parse_plugins(&output),
+        None => Vec::new(),
+    };
+    for required in REQUIRED_PLUGINS {
+        if !plugins.iter().any(|(name, _)| name == required) {
+            tools.push(Tool {
+                name: format!("helm-{required}"),
+                version: None,
+                required: true,
+            });
+        }
+    }
+    for (name, version) in plugins {
+        tools.push(Tool {
+            required: REQUIRED_PLUGINS.contains(&name.as_str()),
+            name: format!("helm-{name}"),
+            version: Some(version),
+        });
+    }
+
+    for tool in tools.iter() {
+        let version = tool.version.clone();
+        match version.as_ref() {
+            Some(version) => tracing::info!("Found {} {version}", tool.name),
+            None if tool.required => tracing::error!("Required tool {} not found", tool.name),
+            None => tracing::warn!("Optional tool {} not found", tool.name),
+        }
+        TOOL_INFO
+            .get_or_create(&ToolLabels {
+                tool: tool.name.clone(),
+                version: version.unwrap_or_else(|| "missing".to_owned()),
+            })
+            .set(1);
+    }
+    check(&tools)
+}
+
+async fn tool(name: &str, args: &[&str], required: bool) -> Tool {
+    Tool {
+        name: name.to_owned(),
+        version: output(name, args).await.and_then(|o| parse_version(&o)),
+        required,
+    }
+}
+
+/// Runs a command and returns its stdout, None if it could not be run or failed
+async fn output(program: &str, args: &[&str]) -> Option<String> {
+    let mut cmd = Command::new(program);
+    cmd.args(args).kill_on_drop(true);
+    match time::timeout(TOOL_TIMEOUT, cmd.output()).await {
+        Ok(Ok(output)) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(Ok(output)) => {
+            tracing::debug!(
+                "{program} {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            None
+        }
+        Ok(Err(err)) => {
+            tracing::debug!("Could not run {program}: {err}");
+            None
+        }
+        Err(_) => {
+            tracing::debug!("Timeout running {program}");
+            None
+        }
+    }
+}
+
+fn parse_version(output: &str) -> Option<String> {
+    VERSION.find(output).map(|m| m.as_str().to_owned())
+}
+
+/// Parses the table printed by `helm plugin list` into names and versions
+fn parse_plugins(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split('\t').map(str::trim);
+            let name = columns.next().filter(|n| !n.is_empty())?;
+            let version = columns.next().unwrap_or_default();
+            Some((name.to_owned(), version.to_owned()))
+        })
+        .collect()
+}
+
+fn check(tools: &[Tool]) -> Result<(), String> {
+    let missing: Vec<_> = tools
+        .iter()
+        .filter(|t| t.required && t.version.is_none())
+        .map(|t| t.name.as_str())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("required tools not found: {}", missing.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("helmfile version 0.162.0\n"),
+            Some("0.162.0".to_owned())
+        );
+        assert_eq!(
+            parse_version("v3.14.2+gc309b6f\n"),
+            Some("v3.14.2+gc309b6f".to_owned())
+        );
+        assert_eq!(
+            parse_version("sops 3.8.1 (latest)\n"),
+            Some("3.8.1".to_owned())
+        );
+        assert_eq!(parse_version("unknown"), None);
+    }
+
+    #[test]
+    fn test_parse_plugins() {
+        let output = "NAME   \tVERSION\tDESCRIPTION\ndiff   \t3.9.5  \tPreview helm upgrade changes as a diff\nsecrets\t4.5.1  \tThis plugin provides secrets values encryption for Helm charts secure storing\n";
+        assert_eq!(
+            parse_plugins(output),
+            vec![
+                ("diff".to_owned(), "3.9.5".to_owned()),
+                ("secrets".to_owned(), "4.5.1".to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_tool() {
+        let found = tool("helmfile-does-not-exist", &["--version"], true).await;
+        assert_eq!(found.version, None);
+        let optional = Tool {
+            name: "sops".to_owned(),
+            version: None,
+            required: false,
+        };
+        assert_eq!(check(&[optional]), Ok(()));
+        assert_eq!(
+            check(&[found]),
+            Err("required tools not found: helmfile-does-not-exist".to_owned())
+        );
+    }
+}
@@ -1,145 +1,1062 @@
-use crate::crd::{DecryptionProviderKind, DeploymentResult, DeploymentStatus};
+use crate::config::Config;
+use crate::crd::{
+    Condition, DecryptionProviderKind, DeploymentResult, DeploymentStatus, DriftDetectionMode,
+    Plan, PruneMode, PruneStatus, PrunedRelease, ReleaseInventoryEntry, ReleaseRemediation,
+    RemediationStatus, RemediationStrategy,
+};
 use crate::error::{Error, Result};
-use crate::helmfile::{HelmfileAdapter, HelmfileResult};
+use crate::exec::{ExecError, Workspace, INTERRUPTED};
+use crate::flux::events::FluxEventForwarder;
+use crate::helmfile::{DiffResult, HelmfileAdapter, HelmfileResult, Release};
 use crate::k8sclient::K8sClient;
-use crate::metrics::{l, NUM_RECONCILES_PENDING};
+use crate::metrics::{
+    l, RevisionLabels, APPLIED_REVISION, ARTIFACT_FETCH_DURATION, DRIFTED, FAILED,
+    LAST_SUCCESSFUL_APPLY, NUM_DRIFTS_DETECTED, NUM_RECONCILES_PENDING, READY, RETRIES, SUSPENDED,
+};
 use crate::store::{ControllerStoreRef, HelmfileState};
-use crate::util::{timestamp_now, NS};
+use crate::util::{timestamp_now, truncate, NS};
+use crate::webhooks::WebhookNotifier;
 use crate::{
-    crd::Helmfile, extcrds::gitrepositories::GitRepository, flux::artifact::FluxSourceAdapter,
-    helmfile,
+    crd::Helmfile,
+    extcrds::gitrepositories::{GitRepository, GitRepositoryStatusConditionsStatus},
+    flux::artifact::FluxSourceAdapter,
+    health, helmfile, redact,
 };
-use kube::api::Patch;
+use chrono::Utc;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::{ObjectMeta, Patch};
+use kube::runtime::events::{Event, EventType};
 use kube::{Resource, ResourceExt};
 use serde_json::json;
+use std::collections::BTreeMap;
 use std::io::Write;
-use tempfile::NamedTempFile;
+use std::time::Instant;
+use tempfile::{NamedTempFile, TempDir};
+use tracing::Instrument;
 
 const SECRETS_KEY_AGE: &str = "age.agekey";
 const SECRETS_ENV_KEY_AGE: &str = "SOPS_AGE_KEY_FILE";
 const ACTION_LABEL: &str = "controller/action";
 const ACTION_LABEL_SYNC: &str = "sync";
+const ACTION_LABEL_APPROVE: &str = "approve";
+pub const APPROVED_REVISION_ANNOTATION: &str = "controller/approved-revision";
+const MAX_PLAN_SIZE: usize = 32 * 1024;
+const MAX_MANIFESTS_SIZE: usize = 512 * 1024;
+const SOURCE_NOT_READY: &str = "source not ready";
+const CONDITION_READY: &str = "Ready";
+const CONDITION_SOURCE_VERIFIED: &str = "SourceVerified";
+const REASON_VERIFICATION_ERROR: &str = "VerificationError";
+const CONDITION_DRIFTED: &str = "Drifted";
+const REASON_HEALTH_CHECK_FAILED: &str = "HealthCheckFailed";
+const DEFAULT_HEALTH_CHECK_TIMEOUT: &str = "5m";
+const MAX_EVENT_NOTE_SIZE: usize = 1000;
 
+// the reasons are only read by tests, the status and events carry them to users
+#[allow(dead_code)]
 pub enum ReconcileResult {
     Success,
     Failed(String),
     FailedRetriesExhausted(String),
     Pending(String),
+    /// the source is suspended, nothing happens until it is resumed
+    Suspended(String),
+    AwaitingApproval(String),
+    /// a helmfile command was terminated by a controller shutdown
+    Interrupted,
+}
+
+/// Receivers of the events of a Helmfile besides the Kubernetes events
+#[derive(Clone, Copy, Default)]
+pub struct Notifiers<'a> {
+    /// the flux notification-controller, gets all events
+    pub events: Option<&'a FluxEventForwarder>,
+    /// the configured webhooks, get the reconcile results and detected drift
+    pub webhooks: Option<&'a WebhookNotifier>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn reconcile_helmfile(
     client: impl K8sClient,
     helmfile_adapter: impl HelmfileAdapter,
     flux_adapter: impl FluxSourceAdapter,
     store: ControllerStoreRef,
+    config: &Config,
     obj: &Helmfile,
     repo: GitRepository,
+    notifiers: Notifiers<'_>,
 ) -> Result<ReconcileResult> {
     let name = obj.name_any();
     let ns = obj.namespace().unwrap_or_else(|| NS.to_owned());
     tracing::info!("Starting reconcile of helmfile {name} in namespace {ns}");
 
+    // place obj in store for reference from watcher
+    {
+        let mut store = store.write().await;
+        store.helmfiles.insert(obj.into(), (*obj).clone());
+    }
+
+    // Check the state of the source before using its artifact
+    let source_name = &obj.spec.source_ref.name;
+    let source_warning = match source_state(&repo) {
+        SourceState::Ready => None,
+        SourceState::Suspended => {
+            SUSPENDED.get_or_create(&l(obj)).set(1);
+            let reason = format!("{SOURCE_NOT_READY}: GitRepository {source_name} is suspended");
+            tracing::info!("{reason}. Waiting for it to be resumed");
+            update_pending_status(&client, obj, &reason).await?;
+            return Ok(ReconcileResult::Suspended(reason));
+        }
+        SourceState::NotReady {
+            message,
+            verification_failed,
+        } => {
+            let reason = format!("{SOURCE_NOT_READY}: {message}");
+            let block = obj
+                .spec
+                .options
+                .as_ref()
+                .and_then(|o| o.block_on_verification_failure)
+                .unwrap_or(false);
+            if verification_failed && block {
+                return pending(&client, obj, reason).await;
+            }
+            Some(reason)
+        }
+    };
+
+    SUSPENDED.get_or_create(&l(obj)).set(0);
+
+    // Retrieve artifact information
+    let Some(artifact) = repo.status.and_then(|el| el.artifact) else {
+        let reason = source_warning.unwrap_or_else(|| {
+            format!("Could not yet find artifact for GitRepository {source_name} in namespace {ns}")
+        });
+        return pending(&client, obj, reason).await;
+    };
+
     let existing_state = {
         let mut store = store.write().await;
         store.state.remove(&obj.into())
     };
     let num_retries = existing_state.as_ref().and_then(|s| s.num_retries);
+    let num_remediations = existing_state.as_ref().and_then(|s| s.num_remediations);
 
     // Prepare any needed secrets
     let (key_file, env) = if let Some(decryption) = obj.spec.decryption.as_ref() {
         let result = match decryption.provider {
             DecryptionProviderKind::SopsAge => {
-                prepare_age_key(&client, &ns, &decryption.secret_ref.name).await?
+                prepare_age_key(&client, obj, &decryption.secret_ref.name, config)
+                    .instrument(tracing::info_span!("prepare_key"))
+                    .await?
             }
         };
         (Some(result.0), Some(result.1))
     } else {
+        redact::remove_secrets(&obj.into());
         (None, None)
     };
 
-    // Retrieve artifact information
-    let Some(artifact) = repo.status.and_then(|el| el.artifact) else {
-        let source_name = &obj.spec.source_ref.name;
-        let reason = format!(
-            "Could not yet find artifact for GitRepository {source_name} in namespace {ns}"
-        );
-        NUM_RECONCILES_PENDING.get_or_create(&l(obj)).inc();
-        tracing::info!("{reason}. Requeuing");
-        return Ok(ReconcileResult::Pending(reason));
-    };
     // download and extract artifact
+    let revision = artifact.revision.clone();
+    tracing::Span::current().record("revision", revision.as_str());
+    let previous_digest = existing_state.as_ref().map(|s| s.current_digest.clone());
+    let fetch_start = Instant::now();
     let (location, digest) = flux_adapter
         .fetch_and_extract_artifact(existing_state, &artifact)
+        .instrument(tracing::info_span!("fetch_artifact"))
         .await?;
+    if previous_digest.as_ref() != Some(&digest) {
+        ARTIFACT_FETCH_DURATION
+            .get_or_create(&l(obj))
+            .observe(fetch_start.elapsed().as_secs_f64());
+        let note = format!("Fetched revision {revision}");
+        publish_event(
+            &client,
+            notifiers,
+            obj,
+            EventType::Normal,
+            "ArtifactFetched",
+            "Fetch",
+            note,
+            Some(&revision),
+        )
+        .await;
+    }
 
     let action = action(obj);
     // Use sync on first run
-    let mode = match (action, obj.status.is_some()) {
-        (Action::None, true) => helmfile::Mode::Apply,
-        (Action::None, false) => helmfile::Mode::Sync,
+    let applied_before = obj.status.as_ref().is_some_and(|s| {
+        s.last_applied_revision.is_some()
+            || matches!(
+                s.status,
+                DeploymentResult::Successful | DeploymentResult::Failed
+            )
+    });
+    let mode = match (action, applied_before) {
+        (Action::None | Action::Approve, true) => helmfile::Mode::Apply,
+        (Action::None | Action::Approve, false) => helmfile::Mode::Sync,
         (Action::Sync, _) => helmfile::Mode::Sync,
     };
 
     // run helmfile
-    let manifest_dir = if let Some(path) = obj.spec.path.as_ref() {
-        location.path().join(path)
-    } else {
-        location.path().to_path_buf()
+    let workspace = workspace(obj, &location, &artifact.url, env);
+
+    // dry runs and revisions waiting for approval only get a plan instead of being applied
+    let dry_run = config.dry_run || obj.spec.dry_run.unwrap_or(false);
+    if dry_run || approval_pending(obj, &revision) {
+        let existing_plan = obj
+            .status
+            .as_ref()
+            .and_then(|s| s.plan.clone())
+            .filter(|p| p.revision == revision && p.manifests_config_map.is_none());
+        let plan = match existing_plan {
+            Some(plan) if !dry_run => Ok(plan),
+            _ => create_plan(&helmfile_adapter, obj, &workspace, &revision).await,
+        };
+        let plan = match plan {
+            Ok(plan) if dry_run => {
+                store_rendered_manifests(&client, &helmfile_adapter, obj, &workspace, plan).await?
+            }
+            plan => plan,
+        };
+        // the plan is made again after an interruption without using up a retry
+        let num_retries = match &plan {
+            Ok(_) => num_retries,
+            Err(result) => update_retries(num_retries, result),
+        };
+        let state = HelmfileState {
+            current_digest: digest,
+            location,
+            artifact_url: artifact.url.clone(),
+            num_retries,
+            num_remediations,
+        };
+        store_state(&store, obj, state).await;
+
+        let mut status = obj.status.clone().unwrap_or_default();
+        let result = match plan {
+            Ok(plan) if dry_run => {
+                let summary = if plan.diff.is_empty() {
+                    "no changes".to_owned()
+                } else {
+                    drift_summary(&plan.diff)
+                };
+                tracing::info!(
+                    "Dry run of revision {revision} of helmfile {name} in namespace {ns}: {summary}"
+                );
+                status.reason = Some(format!("dry run of revision {revision}: {summary}"));
+                status.plan = Some(plan);
+                ReconcileResult::Success
+            }
+            Ok(plan) => {
+                tracing::info!(
+                    "Revision {revision} of helmfile {name} in namespace {ns} is waiting for approval"
+                );
+                status.status = DeploymentResult::AwaitingApproval;
+                status.reason = Some(format!("waiting for approval of revision {revision}"));
+                status.plan = Some(plan);
+                ReconcileResult::AwaitingApproval(revision)
+            }
+            Err(HelmfileResult::Interrupted) => {
+                status.status = DeploymentResult::Interrupted;
+                status.reason = Some(format!("plan of revision {revision} was {INTERRUPTED}"));
+                ReconcileResult::Interrupted
+            }
+            Err(result) => {
+                status.status = DeploymentResult::Failed;
+                if let HelmfileResult::Failed(reason) = &result {
+                    status.reason = Some(reason.clone());
+                }
+                map_result(result, retries_exhausted(obj, num_retries))
+            }
+        };
+        if Some(&status) != obj.status.as_ref() {
+            update_status(&client, &name, &ns, status).await?;
+        }
+        if action != Action::None {
+            remove_action_label(&client, obj, &name, &ns).await?;
+        }
+        drop(key_file);
+        return Ok(result);
+    }
+    let drift_mode = obj
+        .spec
+        .drift_detection
+        .as_ref()
+        .map(|d| d.mode.clone())
+        .unwrap_or_default();
+    let already_applied = obj
+        .status
+        .as_ref()
+        .and_then(|s| s.last_applied_revision.as_ref())
+        .is_some_and(|r| *r == revision);
+    let remediate = remediation_allowed(obj, num_remediations);
+    let (outcome, drift) = async {
+        if mode == helmfile::Mode::Apply
+            && drift_mode != DriftDetectionMode::Disabled
+            && already_applied
+        {
+            // same revision as before, only check for drift
+            match helmfile_adapter.diff(&workspace, obj).await {
+                DiffResult::NoChange => {
+                    (ApplyOutcome::skipped(HelmfileResult::NoChange), Drift::None)
+                }
+                DiffResult::Changes(diff) => {
+                    let summary = drift_summary(&diff);
+                    tracing::info!(
+                        "Detected drift for helmfile {name} in namespace {ns}: {summary}"
+                    );
+                    NUM_DRIFTS_DETECTED.get_or_create(&l(obj)).inc();
+                    let note = format!("Detected drift for revision {revision}: {summary}");
+                    publish_event(
+                        &client,
+                        notifiers,
+                        obj,
+                        EventType::Warning,
+                        "DriftDetected",
+                        "DetectDrift",
+                        note,
+                        Some(&revision),
+                    )
+                    .await;
+                    if drift_mode == DriftDetectionMode::Enabled {
+                        let outcome = apply(
+                            &client,
+                            notifiers,
+                            &helmfile_adapter,
+                            mode,
+                            &workspace,
+                            obj,
+                            &revision,
+                            remediate,
+                        )
+                        .await;
+                        (outcome, Drift::Corrected(summary))
+                    } else {
+                        (
+                            ApplyOutcome::skipped(HelmfileResult::NoChange),
+                            Drift::Detected(summary),
+                        )
+                    }
+                }
+                DiffResult::Failed(reason) => (
+                    ApplyOutcome::skipped(HelmfileResult::Failed(reason)),
+                    Drift::Unknown,
+                ),
+                DiffResult::Interrupted => (
+                    ApplyOutcome::skipped(HelmfileResult::Interrupted),
+                    Drift::Unknown,
+                ),
+            }
+        } else {
+            let outcome = apply(
+                &client,
+                notifiers,
+                &helmfile_adapter,
+                mode,
+                &workspace,
+                obj,
+                &revision,
+                remediate,
+            )
+            .await;
+            (outcome, Drift::Unknown)
+        }
+    }
+    .instrument(tracing::info_span!("helmfile_run", mode = ?mode))
+    .await;
+    let ApplyOutcome {
+        result,
+        remediation,
+        mut inventory,
+    } = outcome;
+    let pruned = match (&result, inventory.as_mut()) {
+        (HelmfileResult::Applied | HelmfileResult::NoChange, Some(inventory))
+            if obj.spec.prune == Some(PruneMode::Releases) =>
+        {
+            prune_releases(
+                &client,
+                notifiers,
+                &helmfile_adapter,
+                obj,
+                &revision,
+                inventory,
+            )
+            .await
+        }
+        _ => None,
     };
-    let result = helmfile_adapter.apply(mode, &manifest_dir, obj, env).await;
     tracing::info!("Got result from helmfile: {:?}", result);
+    let applied = matches!(result, HelmfileResult::Applied | HelmfileResult::NoChange);
+    let health = if applied && health_checks_enabled(obj) {
+        let health = check_health(&client, &helmfile_adapter, obj, inventory.as_deref()).await;
+        if let Err(reason) = &health {
+            tracing::info!("Health check of helmfile {name} in namespace {ns} failed: {reason}");
+        }
+        Some(health)
+    } else {
+        None
+    };
+    let result = match &health {
+        Some(Err(reason)) => HelmfileResult::Failed(format!("health check failed: {reason}")),
+        _ => result,
+    };
     let num_retries = update_retries(num_retries, &result);
-    let exhausted = if let Some(retry) = num_retries {
-        if let Some(allowed_retries) = obj.spec.options.as_ref().and_then(|o| o.retries) {
-            if allowed_retries > 0 {
-                retry >= allowed_retries
+    let exhausted = retries_exhausted(obj, num_retries);
+    publish_result_event(&client, notifiers, obj, &result, &revision, exhausted).await;
+    record_result_metrics(obj, &result, &revision, num_retries);
+    let num_remediations = match (&result, &remediation) {
+        (HelmfileResult::Failed(_), Some(_)) => Some(num_remediations.unwrap_or(0) + 1),
+        (HelmfileResult::Failed(_), None) => num_remediations,
+        (HelmfileResult::Interrupted, _) => num_remediations,
+        _ => None,
+    };
+    let state = HelmfileState {
+        current_digest: digest,
+        location,
+        artifact_url: artifact.url.clone(),
+        num_retries,
+        num_remediations,
+    };
+    store_state(&store, obj, state).await;
+
+    // update status
+    let mut status = obj.status.clone().unwrap_or_default();
+    let previous = status.clone();
+    if applied {
+        status.last_applied_revision = Some(revision.clone());
+        status.plan = None;
+    }
+    match &result {
+        HelmfileResult::Applied | HelmfileResult::NoChange => {
+            status.status = DeploymentResult::Successful;
+            status.reason = source_warning;
+        }
+        HelmfileResult::Failed(reason) => {
+            status.status = DeploymentResult::Failed;
+            status.reason = Some(reason.clone());
+        }
+        HelmfileResult::Interrupted => {
+            status.status = DeploymentResult::Interrupted;
+            status.reason = Some(format!("run of revision {revision} was {INTERRUPTED}"));
+        }
+    }
+    update_ready_condition(obj, &mut status, &result, health);
+    if let Some(inventory) = inventory {
+        status.inventory = inventory.into_iter().map(inventory_entry).collect();
+    }
+    if let Some(releases) = pruned {
+        status.pruned = Some(PruneStatus {
+            revision: revision.clone(),
+            time: timestamp_now(),
+            releases,
+        });
+    }
+    if let Some(releases) = remediation {
+        status.remediation = Some(RemediationStatus {
+            revision: revision.clone(),
+            time: timestamp_now(),
+            releases,
+        });
+    }
+    update_drift_condition(obj, &mut status, &drift_mode, &result, drift);
+    if !matches!(result, HelmfileResult::NoChange) || status != previous {
+        update_status(&client, &name, &ns, status).await?;
+    }
+
+    if action != Action::None {
+        // delete action label
+        remove_action_label(&client, obj, &name, &ns).await?;
+    }
+
+    drop(key_file);
+
+    tracing::info!("Finished reconcile of helmfile {name} in namespace {ns}");
+    Ok(map_result(result, exhausted))
+}
+
+async fn pending(
+    client: &impl K8sClient,
+    obj: &Helmfile,
+    reason: String,
+) -> Result<ReconcileResult> {
+    NUM_RECONCILES_PENDING.get_or_create(&l(obj)).inc();
+    tracing::info!("{reason}. Requeuing");
+    update_pending_status(client, obj, &reason).await?;
+    Ok(ReconcileResult::Pending(reason))
+}
+
+async fn update_pending_status(
+    client: &impl K8sClient,
+    obj: &Helmfile,
+    reason: &str,
+) -> Result<()> {
+    let name = obj.name_any();
+    let ns = obj.namespace().unwrap_or_else(|| NS.to_owned());
+    let mut status = obj.status.clone().unwrap_or_default();
+    status.status = DeploymentResult::Pending;
+    status.reason = Some(reason.to_owned());
+    update_status(client, &name, &ns, status).await
+}
+
+/// Outcome of a drift detection run
+enum Drift {
+    /// no drift detection was done
+    Unknown,
+    None,
+    Detected(String),
+    Corrected(String),
+}
+
+fn update_drift_condition(
+    obj: &Helmfile,
+    status: &mut DeploymentStatus,
+    mode: &DriftDetectionMode,
+    result: &HelmfileResult,
+    drift: Drift,
+) {
+    if *mode == DriftDetectionMode::Disabled {
+        status.conditions.retain(|c| c.type_ != CONDITION_DRIFTED);
+        DRIFTED.remove(&l(obj));
+        return;
+    }
+    let (drifted, reason, message) = match (drift, result) {
+        (Drift::None, _) => (false, "NoDrift", "No drift detected".to_owned()),
+        (Drift::Detected(summary), _) => (true, "DriftDetected", summary),
+        (Drift::Corrected(summary), HelmfileResult::Applied) => (
+            false,
+            "DriftCorrected",
+            format!("Corrected drift: {summary}"),
+        ),
+        (Drift::Corrected(summary), _) => (true, "DriftDetected", summary),
+        (Drift::Unknown, HelmfileResult::Applied) => {
+            (false, "Applied", "Revision was applied".to_owned())
+        }
+        (Drift::Unknown, _) => return,
+    };
+    DRIFTED.get_or_create(&l(obj)).set(drifted as i64);
+    set_condition(status, CONDITION_DRIFTED, drifted, reason, message);
+}
+
+/// Reports the result of the health checks in the Ready condition
+fn update_ready_condition(
+    obj: &Helmfile,
+    status: &mut DeploymentStatus,
+    result: &HelmfileResult,
+    health: Option<std::result::Result<(), String>>,
+) {
+    if !health_checks_enabled(obj) {
+        status.conditions.retain(|c| c.type_ != CONDITION_READY);
+        return;
+    }
+    let (ready, reason, message) = match (health, result) {
+        (Some(Ok(())), _) => (
+            true,
+            "HealthChecksPassed",
+            "All objects are ready".to_owned(),
+        ),
+        (Some(Err(message)), _) => (false, REASON_HEALTH_CHECK_FAILED, message),
+        (None, HelmfileResult::Failed(message)) => (false, "ApplyFailed", message.clone()),
+        (None, HelmfileResult::Interrupted) => (false, "Interrupted", INTERRUPTED.to_owned()),
+        (None, _) => return,
+    };
+    set_condition(status, CONDITION_READY, ready, reason, message);
+}
+
+fn health_checks_enabled(obj: &Helmfile) -> bool {
+    obj.spec.wait.unwrap_or(false)
+        || obj
+            .spec
+            .health_checks
+            .as_ref()
+            .is_some_and(|h| !h.objects.is_empty())
+}
+
+/// Waits for the configured objects and, if wait is set, all objects of the installed releases
+async fn check_health(
+    client: &impl K8sClient,
+    helmfile_adapter: &impl HelmfileAdapter,
+    obj: &Helmfile,
+    releases: Option<&[Release]>,
+) -> std::result::Result<(), String> {
+    let ns = obj.namespace().unwrap_or_else(|| NS.to_owned());
+    let health_checks = obj.spec.health_checks.clone().unwrap_or_default();
+    let mut objects: Vec<_> = health_checks
+        .objects
+        .into_iter()
+        .map(|mut object| {
+            object.namespace.get_or_insert_with(|| ns.clone());
+            object
+        })
+        .collect();
+    if obj.spec.wait.unwrap_or(false) {
+        let releases = releases.ok_or("could not list releases")?;
+        for release in releases.iter().filter(|r| r.revision.is_some()) {
+            let manifest = helmfile_adapter
+                .manifest(release, obj)
+                .await
+                .map_err(|e| format!("could not get manifest of release {}: {e}", release.name))?;
+            let namespace = if release.namespace.is_empty() {
+                &ns
             } else {
-                allowed_retries == 0
+                &release.namespace
+            };
+            objects.extend(health::manifest_objects(&manifest, namespace)?);
+        }
+    }
+    let timeout = health_checks
+        .timeout
+        .unwrap_or_else(|| DEFAULT_HEALTH_CHECK_TIMEOUT.to_owned());
+    let timeout = parse_duration::parse(&timeout)
+        .map_err(|e| format!("invalid health check timeout '{timeout}': {e}"))?;
+    health::wait_for_objects(client, &objects, timeout).await
+}
+
+fn set_condition(
+    status: &mut DeploymentStatus,
+    type_: &str,
+    value: bool,
+    reason: &str,
+    message: String,
+) {
+    let value = if value { "True" } else { "False" };
+    if let Some(condition) = status.conditions.iter_mut().find(|c| c.type_ == type_) {
+        if condition.status != value {
+            condition.status = value.to_owned();
+            condition.last_transition_time = timestamp_now();
+        }
+        condition.reason = reason.to_owned();
+        condition.message = message;
+    } else {
+        status.conditions.push(Condition {
+            type_: type_.to_owned(),
+            status: value.to_owned(),
+            reason: reason.to_owned(),
+            message,
+            last_transition_time: timestamp_now(),
+        });
+    }
+}
+
+/// Summarizes the output of `helmfile diff` by counting the changed resources
+fn drift_summary(diff: &str) -> String {
+    let changed = diff
+        .lines()
+        .filter(|line| {
+            let line = line.trim_end();
+            line.ends_with(" has changed:")
+                || line.ends_with(" has been added:")
+                || line.ends_with(" has been removed:")
+        })
+        .count();
+    if changed > 0 {
+        format!("{changed} resource(s) differ from the helmfile")
+    } else {
+        "Resources differ from the helmfile".to_owned()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SourceState {
+    Ready,
+    NotReady {
+        message: String,
+        verification_failed: bool,
+    },
+    Suspended,
+}
+
+fn source_state(repo: &GitRepository) -> SourceState {
+    if repo.spec.suspend.unwrap_or(false) {
+        return SourceState::Suspended;
+    }
+    let conditions = repo
+        .status
+        .as_ref()
+        .and_then(|s| s.conditions.as_deref())
+        .unwrap_or_default();
+    let failed_verification = conditions.iter().find(|c| {
+        (c.r#type == CONDITION_SOURCE_VERIFIED
+            && matches!(c.status, GitRepositoryStatusConditionsStatus::False))
+            || (c.r#type == CONDITION_READY && c.reason == REASON_VERIFICATION_ERROR)
+    });
+    let not_ready = conditions.iter().find(|c| {
+        c.r#type == CONDITION_READY
+            && matches!(c.status, GitRepositoryStatusConditionsStatus::False)
+    });
+    match (not_ready.or(failed_verification), failed_verification) {
+        (Some(condition), verification) => SourceState::NotReady {
+            message: condition.message.clone(),
+            verification_failed: verification.is_some(),
+        },
+        (None, _) => SourceState::Ready,
+    }
+}
+
+async fn create_plan(
+    helmfile_adapter: &impl HelmfileAdapter,
+    obj: &Helmfile,
+    workspace: &Workspace,
+    revision: &str,
+) -> std::result::Result<Plan, HelmfileResult> {
+    let diff = match helmfile_adapter.diff(workspace, obj).await {
+        DiffResult::NoChange => String::new(),
+        DiffResult::Changes(diff) => truncate(&diff, MAX_PLAN_SIZE),
+        DiffResult::Failed(reason) => return Err(HelmfileResult::Failed(reason)),
+        DiffResult::Interrupted => return Err(HelmfileResult::Interrupted),
+    };
+    Ok(Plan {
+        revision: revision.to_owned(),
+        diff,
+        created: timestamp_now(),
+        manifests_config_map: None,
+    })
+}
+
+/// Renders the helmfile and stores the manifests in a ConfigMap owned by the Helmfile object
+async fn store_rendered_manifests(
+    client: &impl K8sClient,
+    helmfile_adapter: &impl HelmfileAdapter,
+    obj: &Helmfile,
+    workspace: &Workspace,
+    mut plan: Plan,
+) -> Result<std::result::Result<Plan, HelmfileResult>> {
+    let ns = obj.namespace().unwrap_or_else(|| NS.to_owned());
+    let manifests = match helmfile_adapter.template(workspace, obj).await {
+        Ok(manifests) => manifests,
+        Err(ExecError::Interrupted) => return Ok(Err(HelmfileResult::Interrupted)),
+        Err(err) => return Ok(Err(HelmfileResult::Failed(err.to_string()))),
+    };
+    let configmap_name = format!("{}-dry-run", obj.name_any());
+    let configmap = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(configmap_name.clone()),
+            namespace: Some(ns.clone()),
+            owner_references: obj.controller_owner_ref(&()).map(|o| vec![o]),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([
+            ("revision".to_owned(), plan.revision.clone()),
+            (
+                "manifests.yaml".to_owned(),
+                // Secrets are already masked by the helmfile adapter
+                truncate(&manifests, MAX_MANIFESTS_SIZE),
+            ),
+        ])),
+        ..Default::default()
+    };
+    client.apply_configmap(&ns, &configmap).await?;
+    plan.manifests_config_map = Some(configmap_name);
+    Ok(Ok(plan))
+}
+
+fn workspace(
+    obj: &Helmfile,
+    location: &TempDir,
+    artifact_url: &str,
+    key_file: Option<(String, String)>,
+) -> Workspace {
+    let dir = if let Some(path) = obj.spec.path.as_ref() {
+        location.path().join(path)
+    } else {
+        location.path().to_path_buf()
+    };
+    Workspace {
+        dir,
+        artifact_url: artifact_url.to_owned(),
+        path: obj.spec.path.clone(),
+        key_file,
+    }
+}
+
+async fn store_state(store: &ControllerStoreRef, obj: &Helmfile, state: HelmfileState) {
+    let mut store = store.write().await;
+    store.state.insert(obj.into(), state);
+}
+
+struct ApplyOutcome {
+    result: HelmfileResult,
+    /// remediation results if a remediation was attempted
+    remediation: Option<Vec<ReleaseRemediation>>,
+    /// releases after the run, None if they could not be listed
+    inventory: Option<Vec<Release>>,
+}
+
+/// Runs helmfile and lists the resulting releases. If enabled, all releases whose helm
+/// revision changed during a failed run are rolled back.
+#[allow(clippy::too_many_arguments)]
+async fn apply(
+    client: &impl K8sClient,
+    notifiers: Notifiers<'_>,
+    helmfile_adapter: &impl HelmfileAdapter,
+    mode: helmfile::Mode,
+    workspace: &Workspace,
+    obj: &Helmfile,
+    revision: &str,
+    remediate: bool,
+) -> ApplyOutcome {
+    let command = match mode {
+        helmfile::Mode::Apply => "apply",
+        helmfile::Mode::Sync => "sync",
+    };
+    let note = format!("Running helmfile {command} for revision {revision}");
+    publish_event(
+        client,
+        notifiers,
+        obj,
+        EventType::Normal,
+        "ApplyStarted",
+        "Apply",
+        note,
+        Some(revision),
+    )
+    .await;
+    let before = if remediate {
+        match helmfile_adapter.releases(workspace, obj).await {
+            Ok(releases) => Some(releases),
+            Err(err) => {
+                tracing::warn!("Could not list releases, remediation is not possible: {err}");
+                None
             }
-        } else {
-            false
         }
     } else {
-        false
+        None
+    };
+    let result = helmfile_adapter.apply(mode, workspace, obj).await;
+    let after = match helmfile_adapter.releases(workspace, obj).await {
+        Ok(releases) => Some(releases),
+        Err(err) => {
+            tracing::warn!("Could not list releases: {err}");
+            None
+        }
+    };
+    let (HelmfileResult::Failed(_), Some(before), Some(after)) = (&result, before, after.as_ref())
+    else {
+        return ApplyOutcome {
+            result,
+            remediation: None,
+            inventory: after,
+        };
     };
 
-    // store temp dir in store
-    {
-        let mut store = store.write().await;
-        store.state.insert(
-            obj.into(),
-            HelmfileState {
-                current_digest: digest,
-                location,
-                num_retries,
-            },
+    let mut remediations = Vec::new();
+    let mut inventory = after.clone();
+    for release in inventory.iter_mut() {
+        let previous_revision = before
+            .iter()
+            .find(|r| r.name == release.name && r.namespace == release.namespace)
+            .and_then(|r| r.revision);
+        if release.revision == previous_revision {
+            continue;
+        }
+        let (result, message) = if let Some(previous_revision) = previous_revision {
+            tracing::info!(
+                "Rolling back release {} to revision {previous_revision}",
+                release.name
+            );
+            match helmfile_adapter
+                .rollback(release, previous_revision, obj)
+                .await
+            {
+                Ok(()) => ("RolledBack", None),
+                Err(err) => ("RollbackFailed", Some(err)),
+            }
+        } else {
+            (
+                "Skipped",
+                Some("release was newly installed, no revision to roll back to".to_owned()),
+            )
+        };
+        remediations.push(ReleaseRemediation {
+            name: release.name.clone(),
+            namespace: release.namespace.clone(),
+            previous_revision,
+            failed_revision: release.revision,
+            result: result.to_owned(),
+            message,
+        });
+        if result == "RolledBack" {
+            // helm creates a new revision for the rollback
+            release.revision = release.revision.map(|r| r + 1);
+            release.status = Some("deployed".to_owned());
+        }
+    }
+    ApplyOutcome {
+        result,
+        remediation: Some(remediations),
+        inventory: Some(inventory),
+    }
+}
+
+/// Uninstalls all releases of the previous inventory that are no longer part of the helmfile.
+/// Releases that could not be uninstalled are kept in the inventory to be retried.
+async fn prune_releases(
+    client: &impl K8sClient,
+    notifiers: Notifiers<'_>,
+    helmfile_adapter: &impl HelmfileAdapter,
+    obj: &Helmfile,
+    revision: &str,
+    inventory: &mut Vec<Release>,
+) -> Option<Vec<PrunedRelease>> {
+    let previous = obj
+        .status
+        .as_ref()
+        .map(|s| s.inventory.as_slice())
+        .unwrap_or_default();
+    let removed: Vec<_> = previous
+        .iter()
+        .filter(|entry| entry.revision.is_some())
+        .filter(|entry| {
+            !inventory
+                .iter()
+                .any(|r| r.name == entry.name && r.namespace == entry.namespace)
+        })
+        .collect();
+    if removed.is_empty() {
+        return None;
+    }
+
+    let mut pruned = Vec::new();
+    for entry in removed {
+        let release = Release {
+            name: entry.name.clone(),
+            namespace: entry.namespace.clone(),
+            chart: entry.chart.clone(),
+            version: entry.version.clone(),
+            revision: entry.revision,
+            status: entry.status.clone(),
+        };
+        tracing::info!(
+            "Pruning release {} in namespace {} that was removed from helmfile {}",
+            entry.name,
+            entry.namespace,
+            obj.name_any()
         );
+        let (result, message) = match helmfile_adapter.uninstall(&release, obj).await {
+            Ok(()) => ("Uninstalled", None),
+            Err(err) => {
+                inventory.push(release);
+                ("UninstallFailed", Some(err))
+            }
+        };
+        pruned.push(PrunedRelease {
+            name: entry.name.clone(),
+            namespace: entry.namespace.clone(),
+            result: result.to_owned(),
+            message,
+        });
+    }
+    let releases = |result: &str| {
+        pruned
+            .iter()
+            .filter(|p| p.result == result)
+            .map(|p| format!("{}/{}", p.namespace, p.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let uninstalled = releases("Uninstalled");
+    if !uninstalled.is_empty() {
+        let note = format!("Uninstalled releases removed from the helmfile: {uninstalled}");
+        publish_event(
+            client,
+            notifiers,
+            obj,
+            EventType::Normal,
+            "Pruned",
+            "Prune",
+            note,
+            Some(revision),
+        )
+        .await;
+    }
+    let failed = releases("UninstallFailed");
+    if !failed.is_empty() {
+        let note = format!("Could not uninstall releases removed from the helmfile: {failed}");
+        publish_event(
+            client,
+            notifiers,
+            obj,
+            EventType::Warning,
+            "PruneFailed",
+            "Prune",
+            note,
+            Some(revision),
+        )
+        .await;
     }
+    Some(pruned)
+}
 
-    // update status
-    update_status(&client, &name, &ns, &result).await?;
+impl ApplyOutcome {
+    fn skipped(result: HelmfileResult) -> Self {
+        ApplyOutcome {
+            result,
+            remediation: None,
+            inventory: None,
+        }
+    }
+}
 
-    if action != Action::None {
-        // delete action label
-        remove_action_label(&client, obj, &name, &ns).await?;
+fn inventory_entry(release: Release) -> ReleaseInventoryEntry {
+    ReleaseInventoryEntry {
+        name: release.name,
+        namespace: release.namespace,
+        chart: release.chart,
+        version: release.version,
+        revision: release.revision,
+        status: release.status,
     }
+}
 
-    // place obj in store for reference from watcher
-    {
-        let mut store = store.write().await;
-        store.helmfiles.insert(obj.into(), (*obj).clone());
+fn remediation_allowed(obj: &Helmfile, num_remediations: Option<i32>) -> bool {
+    match obj.spec.remediation.as_ref() {
+        Some(remediation) if remediation.strategy == RemediationStrategy::Rollback => {
+            match remediation.retries {
+                Some(retries) if retries >= 0 => num_remediations.unwrap_or(0) < retries,
+                _ => true,
+            }
+        }
+        _ => false,
     }
+}
 
-    drop(key_file);
+fn retries_exhausted(obj: &Helmfile, num_retries: Option<i32>) -> bool {
+    if let Some(retry) = num_retries {
+        if let Some(allowed_retries) = obj.spec.options.as_ref().and_then(|o| o.retries) {
+            if allowed_retries > 0 {
+                retry >= allowed_retries
+            } else {
+                allowed_retries == 0
+            }
+        } else {
+            false
+        }
+    } else {
+        false
+    }
+}
 
-    tracing::info!("Finished reconcile of helmfile {name} in namespace {ns}");
-    Ok(map_result(result, exhausted))
+/// A revision needs approval if approval is required, it was not yet applied and the
+/// approval annotation does not name it
+fn approval_pending(obj: &Helmfile, revision: &str) -> bool {
+    let required = obj.spec.approval.as_ref().is_some_and(|a| a.required);
+    let applied = obj
+        .status
+        .as_ref()
+        .and_then(|s| s.last_applied_revision.as_deref())
+        == Some(revision);
+    let approved = obj
+        .annotations()
+        .get(APPROVED_REVISION_ANNOTATION)
+        .is_some_and(|r| r == revision);
+    required && !applied && !approved
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum Action {
     None,
     Sync,
+    Approve,
 }
 
 fn action(obj: &Helmfile) -> Action {
@@ -147,6 +1064,9 @@ fn action(obj: &Helmfile) -> Action {
         if label == ACTION_LABEL_SYNC {
             return Action::Sync;
         }
+        if label == ACTION_LABEL_APPROVE {
+            return Action::Approve;
+        }
     }
     Action::None
 }
@@ -155,16 +1075,21 @@ fn update_retries(num_retries: Option<i32>, result: &HelmfileResult) -> Option<i
     match result {
         HelmfileResult::Applied | HelmfileResult::NoChange => None,
         HelmfileResult::Failed(_) => Some(num_retries.unwrap_or(0) + 1),
+        // the run did not fail by itself, so it does not use up a retry
+        HelmfileResult::Interrupted => num_retries,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn cleanup_helmfile(
     client: impl K8sClient,
     helmfile_adapter: impl HelmfileAdapter,
     flux_adapter: impl FluxSourceAdapter,
     store: ControllerStoreRef,
+    config: &Config,
     obj: &Helmfile,
     repo: Option<GitRepository>,
+    notifiers: Notifiers<'_>,
 ) -> Result<ReconcileResult> {
     let name = obj.name_any();
     let ns = obj.namespace().unwrap_or_else(|| NS.to_owned());
@@ -174,7 +1099,9 @@ pub async fn cleanup_helmfile(
     let (key_file, env) = if let Some(decryption) = obj.spec.decryption.as_ref() {
         let result = match decryption.provider {
             DecryptionProviderKind::SopsAge => {
-                prepare_age_key(&client, &ns, &decryption.secret_ref.name).await?
+                prepare_age_key(&client, obj, &decryption.secret_ref.name, config)
+                    .instrument(tracing::info_span!("prepare_key"))
+                    .await?
             }
         };
         (Some(result.0), Some(result.1))
@@ -188,29 +1115,77 @@ pub async fn cleanup_helmfile(
     };
 
     // if gitrepo not exists see if last version is still in store
-    let location = if let Some(artifact) = repo.and_then(|r| r.status).and_then(|el| el.artifact) {
-        flux_adapter
+    let (location, artifact_url) = if let Some(artifact) =
+        repo.and_then(|r| r.status).and_then(|el| el.artifact)
+    {
+        let location = flux_adapter
             .fetch_and_extract_artifact(existing_state, &artifact)
+            .instrument(tracing::info_span!("fetch_artifact"))
             .await?
-            .0
+            .0;
+        (location, artifact.url)
     } else if let Some(state) = existing_state {
-        state.location
+        (state.location, state.artifact_url)
     } else {
-        // if neither in store nor gitrepo exists, just quietly end
-        tracing::warn!(
-            "Could not cleanup helmfile {} because source is missing.",
-            name
-        );
+        // if neither in store nor gitrepo exists, fall back to the releases in the inventory
+        let inventory = obj
+            .status
+            .as_ref()
+            .map(|s| s.inventory.as_slice())
+            .unwrap_or_default();
+        if inventory.is_empty() || config.dry_run || obj.spec.dry_run.unwrap_or(false) {
+            tracing::warn!(
+                "Could not cleanup helmfile {} because source is missing.",
+                name
+            );
+        } else {
+            tracing::info!(
+                "Source of helmfile {name} in namespace {ns} is missing, uninstalling releases from inventory"
+            );
+            for entry in inventory.iter().filter(|e| e.revision.is_some()) {
+                let release = Release {
+                    name: entry.name.clone(),
+                    namespace: entry.namespace.clone(),
+                    ..Default::default()
+                };
+                if let Err(err) = helmfile_adapter.uninstall(&release, obj).await {
+                    tracing::warn!("Could not uninstall release {}: {err}", entry.name);
+                }
+            }
+        }
         return Ok(ReconcileResult::Success);
     };
 
     // Run destroy
-    let manifest_dir = if let Some(path) = obj.spec.path.as_ref() {
-        location.path().join(path)
-    } else {
-        location.path().to_path_buf()
-    };
-    let result = helmfile_adapter.destroy(&manifest_dir, obj, env).await;
+    let workspace = workspace(obj, &location, &artifact_url, env);
+    if config.dry_run || obj.spec.dry_run.unwrap_or(false) {
+        tracing::info!("Dry run: skipping destroy of helmfile {name} in namespace {ns}");
+        drop(key_file);
+        return Ok(ReconcileResult::Success);
+    }
+    let result = helmfile_adapter.destroy(&workspace, obj).await;
+    if matches!(result, HelmfileResult::Interrupted) {
+        // keep the finalizer so the destroy is repeated
+        return Err(Error::Interrupted(format!(
+            "helmfile destroy was {INTERRUPTED}"
+        )));
+    }
+    if let HelmfileResult::Failed(reason) = &result {
+        let note = format!("helmfile destroy failed: {reason}");
+        publish_event(
+            &client,
+            notifiers,
+            obj,
+            EventType::Warning,
+            "DestroyFailed",
+            "Destroy",
+            note,
+            obj.status
+                .as_ref()
+                .and_then(|s| s.last_applied_revision.as_deref()),
+        )
+        .await;
+    }
     // TBD: Handle failed destroy and keep location in store
     tracing::info!("Finished cleanup of helmfile {name} in namespace {ns} with result: {result:?}");
 
@@ -222,20 +1197,10 @@ async fn update_status(
     client: &impl K8sClient,
     name: &str,
     namespace: &str,
-    helmfile_status: &HelmfileResult,
+    mut status: DeploymentStatus,
 ) -> Result<()> {
-    let (status, reason) = match helmfile_status {
-        HelmfileResult::NoChange => {
-            return Ok(());
-        }
-        HelmfileResult::Applied => (DeploymentResult::Successful, None),
-        HelmfileResult::Failed(reason) => (DeploymentResult::Failed, Some(reason.clone())),
-    };
-    let status = DeploymentStatus {
-        status,
-        reason,
-        last_update: timestamp_now(),
-    };
+    status.last_update = timestamp_now();
+    redact_status(&mut status);
     let new_status = Patch::Apply(json!({
         "apiVersion": Helmfile::api_version(&()),
         "kind": Helmfile::kind(&()),
@@ -243,14 +1208,161 @@ async fn update_status(
     }));
     client
         .patch_helmfile_status(namespace, name, &new_status)
+        .instrument(tracing::info_span!("update_status"))
         .await?;
     Ok(())
 }
 
-async fn remove_action_label(
-    client: &impl K8sClient,
+/// Updates the state metrics of the Helmfile after a run
+fn record_result_metrics(
     obj: &Helmfile,
-    name: &str,
+    result: &HelmfileResult,
+    revision: &str,
+    num_retries: Option<i32>,
+) {
+    if matches!(result, HelmfileResult::Interrupted) {
+        return;
+    }
+    let labels = l(obj);
+    let succeeded = !matches!(result, HelmfileResult::Failed(_));
+    READY.get_or_create(&labels).set(succeeded as i64);
+    FAILED.get_or_create(&labels).set(!succeeded as i64);
+    RETRIES
+        .get_or_create(&labels)
+        .set(num_retries.unwrap_or(0) as i64);
+    if !succeeded {
+        return;
+    }
+    LAST_SUCCESSFUL_APPLY
+        .get_or_create(&labels)
+        .set(Utc::now().timestamp_millis() as f64 / 1000.0);
+    let revision_labels = |revision: &str| RevisionLabels {
+        namespace: labels.namespace.clone(),
+        name: labels.name.clone(),
+        revision: revision.to_owned(),
+    };
+    if let Some(previous) = obj
+        .status
+        .as_ref()
+        .and_then(|s| s.last_applied_revision.as_deref())
+        .filter(|previous| *previous != revision)
+    {
+        APPLIED_REVISION.remove(&revision_labels(previous));
+    }
+    APPLIED_REVISION
+        .get_or_create(&revision_labels(revision))
+        .set(1);
+}
+
+/// Publishes a kubernetes event for the Helmfile and passes it on to the notifiers,
+/// failures are only logged
+#[allow(clippy::too_many_arguments)]
+async fn publish_event(
+    client: &impl K8sClient,
+    notifiers: Notifiers<'_>,
+    obj: &Helmfile,
+    type_: EventType,
+    reason: &str,
+    action: &str,
+    note: String,
+    revision: Option<&str>,
+) {
+    let event = Event {
+        type_,
+        reason: reason.to_owned(),
+        note: Some(truncate(&redact::redact(&note), MAX_EVENT_NOTE_SIZE)),
+        action: action.to_owned(),
+        secondary: None,
+    };
+    if let Some(events) = notifiers.events {
+        events.forward(obj, &event, revision);
+    }
+    if let Some(webhooks) = notifiers.webhooks {
+        webhooks.notify(obj, &event, revision);
+    }
+    if let Err(err) = client.publish_event(obj, event).await {
+        tracing::warn!("Could not publish event {reason}: {err}");
+    }
+}
+
+async fn publish_result_event(
+    client: &impl K8sClient,
+    notifiers: Notifiers<'_>,
+    obj: &Helmfile,
+    result: &HelmfileResult,
+    revision: &str,
+    exhausted: bool,
+) {
+    let (type_, reason, note) = match result {
+        HelmfileResult::Applied => (
+            EventType::Normal,
+            "Applied",
+            format!("Applied revision {revision}"),
+        ),
+        HelmfileResult::NoChange => (
+            EventType::Normal,
+            "NoChange",
+            format!("No changes for revision {revision}"),
+        ),
+        HelmfileResult::Failed(reason) if exhausted => (
+            EventType::Warning,
+            "RetriesExhausted",
+            format!("Revision {revision} failed and retries are exhausted: {reason}"),
+        ),
+        HelmfileResult::Failed(reason) => (
+            EventType::Warning,
+            "ReconciliationFailed",
+            format!("Revision {revision} failed: {reason}"),
+        ),
+        HelmfileResult::Interrupted => (
+            EventType::Warning,
+            "Interrupted",
+            format!("Run of revision {revision} was {INTERRUPTED}"),
+        ),
+    };
+    publish_event(
+        client,
+        notifiers,
+        obj,
+        type_,
+        reason,
+        "Reconcile",
+        note,
+        Some(revision),
+    )
+    .await;
+}
+
+/// Masks secrets in all fields of the status that contain command output
+fn redact_status(status: &mut DeploymentStatus) {
+    let redact_option = |value: &mut Option<String>| {
+        if let Some(value) = value.as_mut() {
+            *value = redact::redact(value);
+        }
+    };
+    redact_option(&mut status.reason);
+    if let Some(plan) = status.plan.as_mut() {
+        plan.diff = redact::redact(&plan.diff);
+    }
+    for condition in status.conditions.iter_mut() {
+        condition.message = redact::redact(&condition.message);
+    }
+    if let Some(remediation) = status.remediation.as_mut() {
+        for release in remediation.releases.iter_mut() {
+            redact_option(&mut release.message);
+        }
+    }
+    if let Some(pruned) = status.pruned.as_mut() {
+        for release in pruned.releases.iter_mut() {
+            redact_option(&mut release.message);
+        }
+    }
+}
+
+async fn remove_action_label(
+    client: &impl K8sClient,
+    obj: &Helmfile,
+    name: &str,
     namespace: &str,
 ) -> Result<()> {
     if obj.meta().labels.is_some() {
@@ -276,16 +1388,19 @@ fn map_result(value: HelmfileResult, exhausted: bool) -> ReconcileResult {
         (HelmfileResult::NoChange, _) => ReconcileResult::Success,
         (HelmfileResult::Failed(reason), false) => ReconcileResult::Failed(reason),
         (HelmfileResult::Failed(reason), true) => ReconcileResult::FailedRetriesExhausted(reason),
+        (HelmfileResult::Interrupted, _) => ReconcileResult::Interrupted,
     }
 }
 
 async fn prepare_age_key(
     client: &impl K8sClient,
-    namespace: &str,
+    obj: &Helmfile,
     secret_name: &str,
+    config: &Config,
 ) -> Result<(NamedTempFile, (String, String))> {
     // get secret
-    let secret = client.get_secret(namespace, secret_name).await?;
+    let namespace = obj.namespace().unwrap_or_else(|| NS.to_owned());
+    let secret = client.get_secret(&namespace, secret_name).await?;
     let Some(data) = secret.data else {
         return Err(Error::MissingSecret(format!(
             "Could not get data from secret {secret_name}"
@@ -297,8 +1412,11 @@ async fn prepare_age_key(
         )));
     };
 
+    // make sure the key never shows up in any output
+    redact::set_secret(&obj.into(), &String::from_utf8_lossy(&value.0));
+
     // write data to temp file
-    let mut file = tempfile::NamedTempFile::new()?;
+    let mut file = tempfile::NamedTempFile::new_in(config.temp_dir())?;
     file.write_all(&value.0)?;
 
     let env = (
@@ -320,13 +1438,23 @@ mod tests {
     use tempfile::TempDir;
 
     use super::*;
+    use crate::crd::{
+        Approval, Decryption, DriftDetection, HealthCheckRef, HealthChecks, LocalObjectReference,
+        Options, Remediation,
+    };
     use crate::extcrds::gitrepositories::{
         GitRepositorySpec, GitRepositoryStatus, GitRepositoryStatusArtifact,
+        GitRepositoryStatusConditions,
     };
     use crate::flux::artifact::MockFluxSourceAdapter;
     use crate::helmfile::MockHelmfileAdapter;
+    use crate::helmfile::Release;
     use crate::k8sclient::tests::*;
     use crate::store::new_store;
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::ByteString;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
 
     fn minimal_helmfile(name: &str, ns: &str) -> Helmfile {
         Helmfile {
@@ -367,19 +1495,28 @@ mod tests {
 
     #[tokio::test]
     async fn test_cleanup_helmfile_nop() {
-        let client = MockClient::new();
+        let client = mock_client();
         let store = new_store();
         let obj = minimal_helmfile("foo", "bar");
         let helmfile_adapter = MockHelmfileAdapter::new();
         let flux_adapter = MockFluxSourceAdapter::new();
-        let result =
-            cleanup_helmfile(client, helmfile_adapter, flux_adapter, store, &obj, None).await;
+        let result = cleanup_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            None,
+            Notifiers::default(),
+        )
+        .await;
         assert!(matches!(result, Ok(ReconcileResult::Success)));
     }
 
     #[tokio::test]
     async fn test_cleanup_helmfile_withrepo() {
-        let client = MockClient::new();
+        let client = mock_client();
         let store = new_store();
         let (obj, git) = minimal_helmfile_gitrepo("foo", "bar");
 
@@ -393,38 +1530,242 @@ mod tests {
         helmfile_adapter
             .expect_destroy()
             .once()
-            .returning(|_, _, _| HelmfileResult::Applied);
+            .returning(|_, _| HelmfileResult::Applied);
 
         let result = cleanup_helmfile(
             client,
             helmfile_adapter,
             flux_adapter,
             store,
+            &Config::default(),
             &obj,
             Some(git),
+            Notifiers::default(),
         )
         .await;
         assert!(matches!(result, Ok(ReconcileResult::Success)));
     }
 
+    /// Client mock that accepts any published events
+    fn mock_client() -> MockClient {
+        let mut client = MockClient::new();
+        client.expect_publish_event().returning(|_, _| Ok(()));
+        client
+    }
+
+    fn expect_status(client: &mut MockClient, expected: DeploymentResult, reason: &'static str) {
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(move |_, _, patch| match patch {
+                Patch::Apply(v) => {
+                    let status =
+                        serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
+                    status.status == expected
+                        && status.reason.unwrap_or_default().starts_with(reason)
+                }
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+    }
+
+    fn condition(
+        r#type: &str,
+        status: GitRepositoryStatusConditionsStatus,
+        reason: &str,
+        message: &str,
+    ) -> GitRepositoryStatusConditions {
+        GitRepositoryStatusConditions {
+            r#type: r#type.to_owned(),
+            status,
+            reason: reason.to_owned(),
+            message: message.to_owned(),
+            ..Default::default()
+        }
+    }
+
     #[tokio::test]
     async fn test_reconcile_helmfile_no_artifact() {
-        let client = MockClient::new();
+        let mut client = mock_client();
         let store = new_store();
         let (obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
         git.status = None;
 
         let helmfile_adapter = MockHelmfileAdapter::new();
         let flux_adapter = MockFluxSourceAdapter::new();
+        expect_status(&mut client, DeploymentResult::Pending, "Could not yet find");
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Pending(_))));
+    }
+
+    #[test]
+    fn test_source_state() {
+        let (_, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        assert_eq!(source_state(&git), SourceState::Ready);
+
+        git.status.as_mut().unwrap().conditions = Some(vec![condition(
+            CONDITION_READY,
+            GitRepositoryStatusConditionsStatus::False,
+            "GitOperationFailed",
+            "failed to checkout",
+        )]);
+        assert_eq!(
+            source_state(&git),
+            SourceState::NotReady {
+                message: "failed to checkout".to_owned(),
+                verification_failed: false
+            }
+        );
+
+        git.status.as_mut().unwrap().conditions = Some(vec![
+            condition(
+                CONDITION_READY,
+                GitRepositoryStatusConditionsStatus::False,
+                REASON_VERIFICATION_ERROR,
+                "signature invalid",
+            ),
+            condition(
+                CONDITION_SOURCE_VERIFIED,
+                GitRepositoryStatusConditionsStatus::False,
+                REASON_VERIFICATION_ERROR,
+                "signature invalid",
+            ),
+        ]);
+        assert_eq!(
+            source_state(&git),
+            SourceState::NotReady {
+                message: "signature invalid".to_owned(),
+                verification_failed: true
+            }
+        );
+
+        git.spec.suspend = Some(true);
+        assert_eq!(source_state(&git), SourceState::Suspended);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_source_suspended() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        git.spec.suspend = Some(true);
+
+        let helmfile_adapter = MockHelmfileAdapter::new();
+        let flux_adapter = MockFluxSourceAdapter::new();
+        expect_status(&mut client, DeploymentResult::Pending, SOURCE_NOT_READY);
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        // a suspended source is not requeued, resuming it triggers the next reconcile
+        assert!(matches!(result, Ok(ReconcileResult::Suspended(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_block_on_verification_failure() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.options = Some(Options {
+            block_on_verification_failure: Some(true),
+            ..Default::default()
+        });
+        git.status.as_mut().unwrap().conditions = Some(vec![condition(
+            CONDITION_SOURCE_VERIFIED,
+            GitRepositoryStatusConditionsStatus::False,
+            REASON_VERIFICATION_ERROR,
+            "signature invalid",
+        )]);
+
+        let helmfile_adapter = MockHelmfileAdapter::new();
+        let flux_adapter = MockFluxSourceAdapter::new();
+        expect_status(
+            &mut client,
+            DeploymentResult::Pending,
+            "source not ready: signature invalid",
+        );
 
-        let result =
-            reconcile_helmfile(client, helmfile_adapter, flux_adapter, store, &obj, git).await;
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
         assert!(matches!(result, Ok(ReconcileResult::Pending(_))));
     }
 
+    #[tokio::test]
+    async fn test_reconcile_helmfile_source_not_ready_applies_last_artifact() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        git.status.as_mut().unwrap().conditions = Some(vec![condition(
+            CONDITION_READY,
+            GitRepositoryStatusConditionsStatus::False,
+            "GitOperationFailed",
+            "failed to checkout",
+        )]);
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        expect_status(
+            &mut client,
+            DeploymentResult::Successful,
+            "source not ready: failed to checkout",
+        );
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
     #[tokio::test]
     async fn test_reconcile_helmfile_success() {
-        let mut client = MockClient::new();
+        let mut client = mock_client();
         let store = new_store();
         let (obj, git) = minimal_helmfile_gitrepo("foo", "bar");
 
@@ -438,7 +1779,17 @@ mod tests {
         helmfile_adapter
             .expect_apply()
             .once()
-            .returning(|_, _, _, _| HelmfileResult::Applied);
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter.expect_releases().once().returning(|_, _| {
+            Ok(vec![Release {
+                name: "demo".to_owned(),
+                namespace: "bar".to_owned(),
+                chart: "./demo".to_owned(),
+                revision: Some(1),
+                status: Some("deployed".to_owned()),
+                ..Default::default()
+            }])
+        });
         client
             .expect_patch_helmfile_status()
             .once()
@@ -447,13 +1798,1247 @@ mod tests {
                     let status =
                         serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
                     status.status == DeploymentResult::Successful
+                        && status.inventory
+                            == vec![ReleaseInventoryEntry {
+                                name: "demo".to_owned(),
+                                namespace: "bar".to_owned(),
+                                chart: "./demo".to_owned(),
+                                revision: Some(1),
+                                status: Some("deployed".to_owned()),
+                                ..Default::default()
+                            }]
                 }
                 _ => false,
             })
             .returning(|_, _, _| Ok(()));
 
-        let result =
-            reconcile_helmfile(client, helmfile_adapter, flux_adapter, store, &obj, git).await;
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    fn applied_helmfile_gitrepo(mode: DriftDetectionMode) -> (Helmfile, GitRepository) {
+        let (mut obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.drift_detection = Some(DriftDetection { mode });
+        obj.status = Some(DeploymentStatus {
+            status: DeploymentResult::Successful,
+            last_applied_revision: Some("main@sha1:1234".to_owned()),
+            ..Default::default()
+        });
+        git.status
+            .as_mut()
+            .unwrap()
+            .artifact
+            .as_mut()
+            .unwrap()
+            .revision = "main@sha1:1234".to_owned();
+        (obj, git)
+    }
+
+    fn drifted_condition(patch: &Patch<serde_json::Value>) -> Option<Condition> {
+        match patch {
+            Patch::Apply(v) => serde_json::from_value::<DeploymentStatus>(v["status"].clone())
+                .unwrap()
+                .conditions
+                .into_iter()
+                .find(|c| c.type_ == CONDITION_DRIFTED),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_drift_warn() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (obj, git) = applied_helmfile_gitrepo(DriftDetectionMode::Warn);
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter.expect_diff().once().returning(|_, _| {
+            DiffResult::Changes("default, demo, Secret (v1) has changed:\n".to_owned())
+        });
+        helmfile_adapter.expect_apply().never();
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| {
+                drifted_condition(patch).is_some_and(|c| {
+                    c.status == "True" && c.message == "1 resource(s) differ from the helmfile"
+                })
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_drift_interrupted() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (obj, git) = applied_helmfile_gitrepo(DriftDetectionMode::Warn);
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_diff()
+            .once()
+            .returning(|_, _| DiffResult::Interrupted);
+        helmfile_adapter.expect_apply().never();
+        expect_status(
+            &mut client,
+            DeploymentResult::Interrupted,
+            "run of revision",
+        );
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store.clone(),
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Interrupted)));
+        let store = store.read().await;
+        let state = store.state.get(&(&obj).into()).unwrap();
+        assert_eq!(state.num_retries, None);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_drift_enabled() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (obj, git) = applied_helmfile_gitrepo(DriftDetectionMode::Enabled);
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_diff()
+            .once()
+            .returning(|_, _| DiffResult::Changes(String::new()));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| {
+                drifted_condition(patch)
+                    .is_some_and(|c| c.status == "False" && c.reason == "DriftCorrected")
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_drift_new_revision_applies() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (obj, mut git) = applied_helmfile_gitrepo(DriftDetectionMode::Warn);
+        git.status
+            .as_mut()
+            .unwrap()
+            .artifact
+            .as_mut()
+            .unwrap()
+            .revision = "main@sha1:5678".to_owned();
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter.expect_diff().never();
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        expect_status(&mut client, DeploymentResult::Successful, "");
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[test]
+    fn test_drift_summary() {
+        let diff = "default, demo, Secret (v1) has changed:\n  data: ...\ndefault, demo2, ConfigMap (v1) has been added:\n";
+        assert_eq!(
+            drift_summary(diff),
+            "2 resource(s) differ from the helmfile"
+        );
+        assert_eq!(drift_summary(""), "Resources differ from the helmfile");
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_awaiting_approval() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.approval = Some(Approval { required: true });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_diff()
+            .once()
+            .returning(|_, _| DiffResult::Changes("some diff".to_owned()));
+        helmfile_adapter.expect_apply().never();
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| match patch {
+                Patch::Apply(v) => {
+                    let status =
+                        serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
+                    status.status == DeploymentResult::AwaitingApproval
+                        && status.plan.is_some_and(|p| p.diff == "some diff")
+                }
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::AwaitingApproval(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_approved() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        git.status
+            .as_mut()
+            .unwrap()
+            .artifact
+            .as_mut()
+            .unwrap()
+            .revision = "main@sha1:1234".to_owned();
+        obj.spec.approval = Some(Approval { required: true });
+        obj.metadata.labels = Some(BTreeMap::from([(
+            ACTION_LABEL.to_owned(),
+            ACTION_LABEL_APPROVE.to_owned(),
+        )]));
+        obj.metadata.annotations = Some(BTreeMap::from([(
+            APPROVED_REVISION_ANNOTATION.to_owned(),
+            "main@sha1:1234".to_owned(),
+        )]));
+        obj.status = Some(DeploymentStatus {
+            status: DeploymentResult::AwaitingApproval,
+            plan: Some(Plan {
+                revision: "main@sha1:1234".to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter.expect_diff().never();
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| match patch {
+                Patch::Apply(v) => {
+                    let status =
+                        serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
+                    status.status == DeploymentResult::Successful
+                        && status.plan.is_none()
+                        && status.last_applied_revision.as_deref() == Some("main@sha1:1234")
+                }
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+        client
+            .expect_patch_helmfile_metadata()
+            .once()
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_dry_run() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.dry_run = Some(true);
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter.expect_diff().once().returning(|_, _| {
+            DiffResult::Changes("default, demo, Secret (v1) has been added:\n".to_owned())
+        });
+        helmfile_adapter.expect_template().once().returning(|_, _| {
+            Ok("kind: Secret\nmetadata:\n  name: demo\ndata:\n  password: '***'\n".to_owned())
+        });
+        helmfile_adapter.expect_apply().never();
+        client
+            .expect_apply_configmap()
+            .once()
+            .withf(|ns, cm| {
+                let manifests = &cm.data.as_ref().unwrap()["manifests.yaml"];
+                ns == "bar"
+                    && cm.metadata.name.as_deref() == Some("foo-dry-run")
+                    && manifests.contains("kind: Secret")
+            })
+            .returning(|_, _| Ok(()));
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| match patch {
+                Patch::Apply(v) => {
+                    let status =
+                        serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
+                    status.status == DeploymentResult::Pending
+                        && status.reason.is_some_and(|r| r.starts_with("dry run"))
+                        && status.plan.is_some_and(|p| {
+                            p.manifests_config_map.as_deref() == Some("foo-dry-run")
+                        })
+                }
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_helmfile_dry_run() {
+        let client = mock_client();
+        let store = new_store();
+        let (obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        let config = Config {
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter.expect_destroy().never();
+
+        let result = cleanup_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &config,
+            &obj,
+            Some(git),
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    fn release(name: &str, revision: Option<i64>) -> Release {
+        Release {
+            name: name.to_owned(),
+            namespace: "bar".to_owned(),
+            revision,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_rollback() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.remediation = Some(Remediation {
+            strategy: RemediationStrategy::Rollback,
+            retries: None,
+        });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        let mut seq = mockall::Sequence::new();
+        helmfile_adapter
+            .expect_releases()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(vec![release("a", Some(1)), release("b", Some(2))]));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| HelmfileResult::Failed("upgrade failed".to_owned()));
+        helmfile_adapter
+            .expect_releases()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| {
+                Ok(vec![
+                    release("a", Some(2)),
+                    release("b", Some(2)),
+                    release("c", Some(1)),
+                ])
+            });
+        helmfile_adapter
+            .expect_rollback()
+            .once()
+            .withf(|release, revision, _| release.name == "a" && *revision == 1)
+            .returning(|_, _, _| Ok(()));
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| match patch {
+                Patch::Apply(v) => {
+                    let status =
+                        serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
+                    let results: Vec<_> = status
+                        .remediation
+                        .unwrap()
+                        .releases
+                        .into_iter()
+                        .map(|r| (r.name, r.result))
+                        .collect();
+                    status.status == DeploymentResult::Failed
+                        && results
+                            == vec![
+                                ("a".to_owned(), "RolledBack".to_owned()),
+                                ("c".to_owned(), "Skipped".to_owned()),
+                            ]
+                }
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store.clone(),
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Failed(_))));
+        let store = store.read().await;
+        let state = store.state.get(&(&obj).into()).unwrap();
+        assert_eq!(state.num_remediations, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_interrupted() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.remediation = Some(Remediation {
+            strategy: RemediationStrategy::Rollback,
+            retries: None,
+        });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        let mut seq = mockall::Sequence::new();
+        helmfile_adapter
+            .expect_releases()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(vec![release("a", Some(1))]));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| HelmfileResult::Interrupted);
+        helmfile_adapter
+            .expect_releases()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(vec![release("a", Some(2))]));
+        // no rollback of an interrupted run
+        helmfile_adapter.expect_rollback().never();
+        expect_status(
+            &mut client,
+            DeploymentResult::Interrupted,
+            "run of revision",
+        );
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store.clone(),
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Interrupted)));
+        let store = store.read().await;
+        let state = store.state.get(&(&obj).into()).unwrap();
+        assert_eq!(state.num_retries, None);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_helmfile_interrupted() {
+        let (obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_destroy()
+            .once()
+            .returning(|_, _| HelmfileResult::Interrupted);
+
+        let result = cleanup_helmfile(
+            mock_client(),
+            helmfile_adapter,
+            flux_adapter,
+            new_store(),
+            &Config::default(),
+            &obj,
+            Some(git),
+            Notifiers::default(),
+        )
+        .await;
+        // the finalizer is kept so the destroy is repeated
+        assert!(matches!(result, Err(Error::Interrupted(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_prune_releases() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.prune = Some(PruneMode::Releases);
+        let entry = |name: &str, revision| ReleaseInventoryEntry {
+            name: name.to_owned(),
+            namespace: "bar".to_owned(),
+            revision,
+            ..Default::default()
+        };
+        obj.status = Some(DeploymentStatus {
+            status: DeploymentResult::Successful,
+            inventory: vec![
+                entry("kept", Some(1)),
+                entry("removed", Some(2)),
+                entry("broken", Some(1)),
+                entry("never-installed", None),
+            ],
+            ..Default::default()
+        });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .once()
+            .returning(|_, _| Ok(vec![release("kept", Some(2))]));
+        helmfile_adapter
+            .expect_uninstall()
+            .once()
+            .withf(|release, _| release.name == "removed")
+            .returning(|_, _| Ok(()));
+        helmfile_adapter
+            .expect_uninstall()
+            .once()
+            .withf(|release, _| release.name == "broken")
+            .returning(|_, _| Err("uninstall failed".to_owned()));
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| match patch {
+                Patch::Apply(v) => {
+                    let status =
+                        serde_json::from_value::<DeploymentStatus>(v["status"].clone()).unwrap();
+                    let pruned: Vec<_> = status
+                        .pruned
+                        .unwrap()
+                        .releases
+                        .into_iter()
+                        .map(|r| (r.name, r.result))
+                        .collect();
+                    let inventory: Vec<_> = status.inventory.into_iter().map(|e| e.name).collect();
+                    pruned
+                        == vec![
+                            ("removed".to_owned(), "Uninstalled".to_owned()),
+                            ("broken".to_owned(), "UninstallFailed".to_owned()),
+                        ]
+                        && inventory == vec!["kept".to_owned(), "broken".to_owned()]
+                }
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+        client
+            .expect_patch_helmfile_metadata()
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_events() {
+        let mut client = MockClient::new();
+        let store = new_store();
+        let (obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Failed("release demo failed".to_owned()));
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        client
+            .expect_patch_helmfile_status()
+            .returning(|_, _, _| Ok(()));
+        client
+            .expect_patch_helmfile_metadata()
+            .returning(|_, _, _| Ok(()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let published = events.clone();
+        client.expect_publish_event().returning(move |_, event| {
+            published.lock().unwrap().push((
+                event.type_,
+                event.reason,
+                event.note.unwrap_or_default(),
+            ));
+            Ok(())
+        });
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(result.is_ok());
+        let events = events.lock().unwrap();
+        let reasons: Vec<_> = events
+            .iter()
+            .map(|(_, reason, _)| reason.as_str())
+            .collect();
+        assert_eq!(
+            reasons,
+            vec!["ArtifactFetched", "ApplyStarted", "ReconciliationFailed"]
+        );
+        let (type_, _, note) = events.last().unwrap();
+        assert_eq!(*type_, EventType::Warning);
+        assert!(note.ends_with("failed: release demo failed"));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_notifiers() {
+        // local stand-in for a webhook receiver and the notification-controller
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+        let (flux_sender, mut flux_receiver) =
+            tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+        let app = axum::Router::new()
+            .route(
+                "/hook",
+                axum::routing::post(
+                    move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        sender.send(body).unwrap();
+                    },
+                ),
+            )
+            .route(
+                "/events",
+                axum::routing::post(
+                    move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        flux_sender.send(body).unwrap();
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut config = NamedTempFile::new().unwrap();
+        writeln!(config, "webhooks:\n- url: http://{address}/hook").unwrap();
+        let webhooks = WebhookNotifier::load(config.path()).unwrap();
+        let events =
+            FluxEventForwarder::new(format!("http://{address}/events").parse().unwrap(), None)
+                .unwrap();
+
+        let mut client = mock_client();
+        let (obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Failed("release demo failed".to_owned()));
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        expect_status(&mut client, DeploymentResult::Failed, "release demo failed");
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            new_store(),
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers {
+                events: Some(&events),
+                webhooks: Some(&webhooks),
+            },
+        )
+        .await;
+        assert!(result.is_ok());
+        // only the result is sent, not the progress events
+        let payload = receiver.recv().await.unwrap();
+        assert_eq!(payload["name"], "foo");
+        assert_eq!(payload["reason"], "ReconciliationFailed");
+        assert_eq!(payload["result"], "failed");
+        assert!(receiver.try_recv().is_err());
+        // flux gets the progress events as well
+        let mut reasons = vec![];
+        while reasons.last() != Some(&"ReconciliationFailed".to_owned()) {
+            let body = flux_receiver.recv().await.unwrap();
+            assert_eq!(body["involvedObject"]["name"], "foo");
+            reasons.push(body["reason"].as_str().unwrap().to_owned());
+        }
+        assert!(reasons.len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_spans() {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+        use opentelemetry_sdk::trace::TracerProvider;
+        use tracing_subscriber::prelude::*;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut client = mock_client();
+        let (obj, mut git) = minimal_helmfile_gitrepo("foo", "bar");
+        git.status
+            .as_mut()
+            .unwrap()
+            .artifact
+            .as_mut()
+            .unwrap()
+            .revision = "main@sha1:1234".to_owned();
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        client
+            .expect_patch_helmfile_status()
+            .returning(|_, _, _| Ok(()));
+
+        let span = tracing::info_span!(
+            "helmfile",
+            revision = tracing::field::Empty,
+            result = tracing::field::Empty
+        );
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            new_store(),
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .instrument(span.clone())
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+        span.record("result", "success");
+        drop(span);
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let root = spans.iter().find(|s| s.name == "helmfile").unwrap();
+        let attribute = |key: &str| {
+            root.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+        assert_eq!(attribute("revision"), Some("main@sha1:1234".to_owned()));
+        assert_eq!(attribute("result"), Some("success".to_owned()));
+        let mut children: Vec<_> = spans
+            .iter()
+            .filter(|s| s.parent_span_id == root.span_context.span_id())
+            .map(|s| s.name.as_ref())
+            .collect();
+        children.sort();
+        assert_eq!(
+            children,
+            vec!["fetch_artifact", "helmfile_run", "update_status"]
+        );
+    }
+
+    #[test]
+    fn test_record_result_metrics() {
+        let mut obj = minimal_helmfile("metrics", "bar");
+        obj.status = Some(DeploymentStatus {
+            last_applied_revision: Some("main@sha1:1111".to_owned()),
+            ..Default::default()
+        });
+        let labels = l(&obj);
+        let revision = |revision: &str| RevisionLabels {
+            namespace: "bar".to_owned(),
+            name: "metrics".to_owned(),
+            revision: revision.to_owned(),
+        };
+        APPLIED_REVISION
+            .get_or_create(&revision("main@sha1:1111"))
+            .set(1);
+
+        record_result_metrics(
+            &obj,
+            &HelmfileResult::Failed("boom".to_owned()),
+            "main@sha1:2222",
+            Some(2),
+        );
+        assert_eq!(READY.get_or_create(&labels).get(), 0);
+        assert_eq!(FAILED.get_or_create(&labels).get(), 1);
+        assert_eq!(RETRIES.get_or_create(&labels).get(), 2);
+
+        record_result_metrics(&obj, &HelmfileResult::Applied, "main@sha1:2222", None);
+        assert_eq!(READY.get_or_create(&labels).get(), 1);
+        assert_eq!(FAILED.get_or_create(&labels).get(), 0);
+        assert_eq!(RETRIES.get_or_create(&labels).get(), 0);
+        assert!(LAST_SUCCESSFUL_APPLY.get_or_create(&labels).get() > 0.0);
+        assert_eq!(
+            APPLIED_REVISION
+                .get_or_create(&revision("main@sha1:2222"))
+                .get(),
+            1
+        );
+        // the series of the previous revision is removed
+        assert_eq!(
+            APPLIED_REVISION
+                .get_or_create(&revision("main@sha1:1111"))
+                .get(),
+            0
+        );
+    }
+
+    fn ready_condition(patch: &Patch<serde_json::Value>) -> Option<Condition> {
+        match patch {
+            Patch::Apply(v) => serde_json::from_value::<DeploymentStatus>(v["status"].clone())
+                .unwrap()
+                .conditions
+                .into_iter()
+                .find(|c| c.type_ == CONDITION_READY),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_health_check_failed() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.health_checks = Some(HealthChecks {
+            timeout: Some("0s".to_owned()),
+            objects: vec![HealthCheckRef {
+                api_version: "apps/v1".to_owned(),
+                kind: "Deployment".to_owned(),
+                name: "demo".to_owned(),
+                namespace: None,
+            }],
+        });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        client
+            .expect_get_object()
+            .once()
+            .withf(|object| object.namespace.as_deref() == Some("bar"))
+            .returning(|_| {
+                Ok(Some(
+                    serde_json::from_value(json!({
+                        "metadata": {"name": "demo"},
+                        "spec": {"replicas": 2},
+                        "status": {"replicas": 2, "updatedReplicas": 1}
+                    }))
+                    .unwrap(),
+                ))
+            });
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| {
+                ready_condition(patch).is_some_and(|c| {
+                    c.status == "False"
+                        && c.reason == REASON_HEALTH_CHECK_FAILED
+                        && c.message.contains("Deployment/bar/demo")
+                })
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Failed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_wait_for_releases() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.wait = Some(true);
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .returning(|_, _, _| HelmfileResult::Applied);
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![release("demo", Some(1))]));
+        helmfile_adapter.expect_manifest().once().returning(|_, _| {
+            Ok("apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: demo\n".to_owned())
+        });
+        client
+            .expect_get_object()
+            .once()
+            .withf(|object| object.kind == "ConfigMap" && object.name == "demo")
+            .returning(|_| {
+                Ok(Some(
+                    serde_json::from_value(json!({"metadata": {"name": "demo"}})).unwrap(),
+                ))
+            });
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| ready_condition(patch).is_some_and(|c| c.status == "True"))
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Success)));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_helmfile_redacts_decryption_key() {
+        let mut client = mock_client();
+        let store = new_store();
+        let (mut obj, git) = minimal_helmfile_gitrepo("foo", "bar");
+        obj.spec.decryption = Some(Decryption {
+            provider: DecryptionProviderKind::SopsAge,
+            secret_ref: LocalObjectReference {
+                name: "sops-age-key".to_owned(),
+            },
+        });
+
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let mut flux_adapter = MockFluxSourceAdapter::new();
+        flux_adapter
+            .expect_fetch_and_extract_artifact()
+            .once()
+            .returning(|_, _| Ok((TempDir::new().unwrap(), "digest".to_string())));
+        client.expect_get_secret().once().returning(|_, _| {
+            Ok(Secret {
+                data: Some(BTreeMap::from([(
+                    SECRETS_KEY_AGE.to_owned(),
+                    ByteString(b"# public key: age1xyz\nsecret-decryption-value\n".to_vec()),
+                )])),
+                ..Default::default()
+            })
+        });
+        helmfile_adapter
+            .expect_apply()
+            .once()
+            .withf(|_, workspace, _| workspace.key_file.is_some())
+            .returning(|_, _, _| {
+                HelmfileResult::Failed("could not decrypt secret-decryption-value".to_owned())
+            });
+        helmfile_adapter
+            .expect_releases()
+            .returning(|_, _| Ok(vec![]));
+        client
+            .expect_patch_helmfile_status()
+            .once()
+            .withf(|_, _, patch| match patch {
+                Patch::Apply(v) => v["status"]["reason"] == "could not decrypt ***",
+                _ => false,
+            })
+            .returning(|_, _, _| Ok(()));
+
+        let result = reconcile_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            git,
+            Notifiers::default(),
+        )
+        .await;
+        assert!(matches!(result, Ok(ReconcileResult::Failed(_))));
+    }
+
+    #[test]
+    fn test_remediation_allowed() {
+        let (mut obj, _) = minimal_helmfile_gitrepo("foo", "bar");
+        assert!(!remediation_allowed(&obj, None));
+        obj.spec.remediation = Some(Remediation {
+            strategy: RemediationStrategy::Rollback,
+            retries: Some(2),
+        });
+        assert!(remediation_allowed(&obj, None));
+        assert!(remediation_allowed(&obj, Some(1)));
+        assert!(!remediation_allowed(&obj, Some(2)));
+        obj.spec.remediation.as_mut().unwrap().retries = Some(-1);
+        assert!(remediation_allowed(&obj, Some(100)));
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_helmfile_from_inventory() {
+        let client = mock_client();
+        let store = new_store();
+        let mut obj = minimal_helmfile("foo", "bar");
+        obj.status = Some(DeploymentStatus {
+            inventory: vec![
+                ReleaseInventoryEntry {
+                    name: "installed".to_owned(),
+                    namespace: "bar".to_owned(),
+                    revision: Some(3),
+                    ..Default::default()
+                },
+                ReleaseInventoryEntry {
+                    name: "not-installed".to_owned(),
+                    namespace: "bar".to_owned(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        });
+        let mut helmfile_adapter = MockHelmfileAdapter::new();
+        let flux_adapter = MockFluxSourceAdapter::new();
+        helmfile_adapter
+            .expect_uninstall()
+            .once()
+            .withf(|release, _| release.name == "installed")
+            .returning(|_, _| Ok(()));
+
+        let result = cleanup_helmfile(
+            client,
+            helmfile_adapter,
+            flux_adapter,
+            store,
+            &Config::default(),
+            &obj,
+            None,
+            Notifiers::default(),
+        )
+        .await;
         assert!(matches!(result, Ok(ReconcileResult::Success)));
     }
 }
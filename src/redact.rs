@@ -0,0 +1,163 @@
+use crate::store::NamespacedName;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+
+const MASK: &str = "***";
+/// shorter values are not masked to avoid destroying unrelated output
+const MIN_SECRET_LEN: usize = 6;
+
+lazy_static! {
+    static ref AGE_SECRET_KEY: Regex = Regex::new("AGE-SECRET-KEY-1[0-9A-Z]+").unwrap();
+    static ref REDACTOR: RwLock<Redactor> = RwLock::new(Redactor::default());
+}
+
+/// Masks known secret values and configured patterns in command output before it is
+/// written to the status, logs or events
+#[derive(Default)]
+pub struct Redactor {
+    /// secret values by the Helmfile they were loaded for
+    secrets: BTreeMap<NamespacedName, BTreeSet<String>>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    fn set_secret(&mut self, owner: &NamespacedName, value: &str) {
+        let lines = value
+            .lines()
+            .map(str::trim)
+            // age key files contain comments with the public key
+            .filter(|line| line.len() >= MIN_SECRET_LEN && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect();
+        self.secrets.insert(owner.clone(), lines);
+    }
+
+    fn redact(&self, input: &str) -> String {
+        let mut result = AGE_SECRET_KEY.replace_all(input, MASK).into_owned();
+        let mut secrets: Vec<_> = self.secrets.values().flatten().collect();
+        // replace longer secrets first in case one secret contains another
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        for secret in secrets {
+            if result.contains(secret.as_str()) {
+                result = result.replace(secret.as_str(), MASK);
+            }
+        }
+        for pattern in self.patterns.iter() {
+            result = pattern.replace_all(&result, MASK).into_owned();
+        }
+        result
+    }
+}
+
+/// Registers the secret value loaded for a Helmfile, replacing the previous one. Each line is
+/// masked separately in the output of all Helmfiles.
+pub fn set_secret(owner: &NamespacedName, value: &str) {
+    REDACTOR.write().unwrap().set_secret(owner, value);
+}
+
+/// Forgets the secret values of a Helmfile
+pub fn remove_secrets(owner: &NamespacedName) {
+    REDACTOR.write().unwrap().secrets.remove(owner);
+}
+
+/// Sets the configured patterns, every match is masked
+pub fn set_patterns(patterns: Vec<Regex>) {
+    REDACTOR.write().unwrap().patterns = patterns;
+}
+
+pub fn redact(input: &str) -> String {
+    REDACTOR.read().unwrap().redact(input)
+}
+
+/// Masks the values of all Secret manifests in rendered yaml before it is stored, then redacts
+/// the rest. Documents that can not be parsed are dropped if they might contain a Secret.
+pub fn redact_manifests(manifests: &str) -> String {
+    let mut documents = Vec::new();
+    let mut document = String::new();
+    for line in manifests.lines() {
+        if line == "---" || line.starts_with("--- ") {
+            documents.push(std::mem::take(&mut document));
+        } else {
+            document.push_str(line);
+            document.push('\n');
+        }
+    }
+    documents.push(document);
+    let masked: Vec<_> = documents
+        .into_iter()
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| mask_secret(&d))
+        .collect();
+    redact(&masked.join("---\n"))
+}
+
+/// Masks the data of a Secret manifest, other documents are returned unchanged
+fn mask_secret(document: &str) -> String {
+    let value: serde_yaml::Value = match serde_yaml::from_str(document) {
+        Ok(value) => value,
+        Err(_) if document.contains("Secret") => {
+            return "# removed, could not be parsed and might contain a Secret\n".to_owned()
+        }
+        Err(_) => return document.to_owned(),
+    };
+    if value.get("kind").and_then(|k| k.as_str()) != Some("Secret") {
+        return document.to_owned();
+    }
+    let mut value = value;
+    for field in ["data", "stringData"] {
+        if let Some(serde_yaml::Value::Mapping(data)) = value.get_mut(field) {
+            for (_, v) in data.iter_mut() {
+                *v = serde_yaml::Value::String(MASK.to_owned());
+            }
+        }
+    }
+    // keep comments like the source template of helm
+    let comments: String = document
+        .lines()
+        .take_while(|l| l.starts_with('#'))
+        .map(|l| format!("{l}\n"))
+        .collect();
+    let yaml = serde_yaml::to_string(&value)
+        .unwrap_or_else(|_| "# removed, could not be masked\n".to_owned());
+    format!("{comments}{yaml}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let owner = NamespacedName {
+            name: "foo".to_owned(),
+            namespace: "bar".to_owned(),
+        };
+        let mut redactor = Redactor::default();
+        redactor.set_secret(&owner, "rotated-secret\n");
+        redactor.set_secret(&owner, "# public key: age1abc\nmy-decrypted-secret\nabc\n");
+        redactor.patterns = vec![Regex::new(r"password=\S+").unwrap()];
+        assert_eq!(
+            redactor.redact(
+                "key AGE-SECRET-KEY-1QQPZRY9X8GF2TVDW0S3JN54KHCE6MUA7L\nvalue my-decrypted-secret, abc, # public key: age1abc\nurl?password=hunter2 done"
+            ),
+            "key ***\nvalue ***, abc, # public key: age1abc\nurl?*** done"
+        );
+        // replaced secrets are no longer kept
+        assert_eq!(redactor.secrets[&owner].len(), 1);
+    }
+
+    #[test]
+    fn test_redact_manifests() {
+        let manifests = "---\n# Source: demo/templates/secret.yaml\napiVersion: v1\nkind: Secret\nmetadata:\n  name: demo\ndata:\n  password: aHVudGVyMg==\nstringData:\n  token: s3cr3t-t0ken\n---\n# Source: demo/templates/configmap.yaml\napiVersion: v1\nkind: ConfigMap\ndata:\n  greeting: hello\n---\nkind: Secret\ndata: [unparsable\n";
+        let redacted = redact_manifests(manifests);
+        assert!(!redacted.contains("aHVudGVyMg=="));
+        assert!(!redacted.contains("s3cr3t-t0ken"));
+        assert!(!redacted.contains("unparsable"));
+        assert!(redacted
+            .contains("# Source: demo/templates/secret.yaml\napiVersion: v1\nkind: Secret\n"));
+        assert!(redacted.contains("password: '***'"));
+        assert!(redacted.contains("# Source: demo/templates/configmap.yaml\napiVersion: v1\nkind: ConfigMap\ndata:\n  greeting: hello\n"));
+    }
+}
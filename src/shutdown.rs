@@ -0,0 +1,137 @@
+use futures::Future;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{oneshot, watch};
+
+/// Coordinates the graceful shutdown. Once started no new reconciles are accepted and helmfile
+/// processes still running after the drain timeout are terminated.
+#[derive(Clone)]
+pub struct Shutdown {
+    started: watch::Sender<bool>,
+    terminate: watch::Sender<bool>,
+    drain_timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(drain_timeout: Duration) -> Self {
+        Self {
+            started: watch::Sender::new(false),
+            terminate: watch::Sender::new(false),
+            drain_timeout,
+        }
+    }
+
+    /// Starts the shutdown on SIGTERM or SIGINT
+    pub fn start_on_signal(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            terminated().await;
+            shutdown.start("received termination signal");
+        });
+    }
+
+    /// Starts draining, later calls are ignored
+    pub fn start(&self, reason: &str) {
+        if self.started.send_replace(true) {
+            return;
+        }
+        let drain_timeout = self.drain_timeout;
+        tracing::info!(
+            "Shutting down ({reason}), waiting up to {drain_timeout:?} for running reconciles"
+        );
+        let terminate = self.terminate.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(drain_timeout).await;
+            tracing::warn!(
+                "Running reconciles did not finish in time, terminating helmfile processes"
+            );
+            terminate.send_replace(true);
+        });
+    }
+
+    /// Aborts once the leader lease is lost. The lease expires a few seconds later and a standby
+    /// takes over, so running helmfile processes can not be drained like on SIGTERM.
+    pub fn abort_on_lost_lease(&self, lost: oneshot::Receiver<()>) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            if lost.await.is_ok() {
+                shutdown.abort("lost leadership");
+            }
+        });
+    }
+
+    /// Stops accepting reconciles and terminates running helmfile processes right away
+    pub fn abort(&self, reason: &str) {
+        self.started.send_replace(true);
+        if !self.terminate.send_replace(true) {
+            tracing::warn!("Shutting down ({reason}), terminating running helmfile processes");
+        }
+    }
+
+    /// Resolves once the shutdown was started
+    pub fn started(&self) -> impl Future<Output = ()> + Send + Sync + 'static {
+        let mut started = self.started.subscribe();
+        async move {
+            let _ = started.wait_for(|s| *s).await;
+        }
+    }
+
+    /// Becomes true once running helmfile processes have to be terminated
+    pub fn terminate(&self) -> watch::Receiver<bool> {
+        self.terminate.subscribe()
+    }
+}
+
+/// Resolves on SIGTERM or SIGINT
+async fn terminated() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Could not listen for SIGTERM");
+    tokio::select! {
+        _ = sigterm.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let shutdown = Shutdown::new(Duration::from_millis(300));
+        let mut terminate = shutdown.terminate();
+        let started = shutdown.started();
+        shutdown.start("test");
+        started.await;
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        // a second start does not restart the drain timeout
+        shutdown.start("test");
+        assert!(!*terminate.borrow_and_update());
+        tokio::time::timeout(Duration::from_millis(200), terminate.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(*terminate.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_lost_lease() {
+        let shutdown = Shutdown::new(Duration::from_secs(300));
+        let mut terminate = shutdown.terminate();
+        let (lost, receiver) = oneshot::channel();
+        shutdown.abort_on_lost_lease(receiver);
+        // a drain that is already running is cut short as well
+        shutdown.start("test");
+        assert!(!*terminate.borrow_and_update());
+
+        lost.send(()).unwrap();
+        // running processes are terminated without waiting for the drain timeout
+        tokio::time::timeout(Duration::from_millis(200), terminate.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(*terminate.borrow());
+        tokio::time::timeout(Duration::from_millis(200), shutdown.started())
+            .await
+            .unwrap();
+    }
+}
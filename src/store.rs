@@ -9,7 +9,10 @@ pub type ControllerStoreRef = Arc<RwLock<ControllerStore>>;
 pub struct HelmfileState {
     pub current_digest: String,
     pub location: TempDir,
+    /// url of the artifact extracted to location
+    pub artifact_url: String,
     pub num_retries: Option<i32>,
+    pub num_remediations: Option<i32>,
 }
 
 pub fn new_store() -> ControllerStoreRef {
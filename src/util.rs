@@ -28,3 +28,38 @@ pub fn timestamp_now() -> String {
 pub fn _parse_timestamp(input: &str) -> Option<DateTime<Utc>> {
     DateTime::parse_from_rfc3339(input).ok().map(|dt| dt.into())
 }
+
+/// Cuts the input to at most `max_len` bytes and marks it as truncated
+pub fn truncate(input: &str, max_len: usize) -> String {
+    if input.len() <= max_len {
+        return input.to_owned();
+    }
+    let mut end = max_len;
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n...(truncated)", &input[..end])
+}
+
+/// Keeps at most the last `max_len` bytes of the input and marks it as truncated
+pub fn truncate_start(input: &str, max_len: usize) -> String {
+    if input.len() <= max_len {
+        return input.to_owned();
+    }
+    let mut start = input.len() - max_len;
+    while !input.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("(truncated)...\n{}", &input[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_start() {
+        assert_eq!(truncate_start("abcdef", 10), "abcdef");
+        assert_eq!(truncate_start("abcdef", 2), "(truncated)...\nef");
+    }
+}
@@ -0,0 +1,311 @@
+use crate::crd::Helmfile;
+use crate::error::{Error, Result};
+use crate::util::NS;
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use kube::runtime::events::Event;
+use kube::ResourceExt;
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use url::Url;
+
+const QUEUE_SIZE: usize = 100;
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+/// Webhooks to notify about reconcile results, loaded from a yaml file (usually a mounted ConfigMap)
+#[derive(Deserialize, Debug, Default)]
+pub struct WebhooksConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookConfig {
+    pub url: Url,
+    /// results to send, all if empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    /// file with the key to sign payloads with HMAC-SHA256
+    pub secret_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Failed,
+    Succeeded,
+    Drifted,
+}
+
+impl WebhookEvent {
+    fn from_reason(reason: &str) -> Option<Self> {
+        match reason {
+            "Applied" => Some(WebhookEvent::Succeeded),
+            "ReconciliationFailed" | "RetriesExhausted" => Some(WebhookEvent::Failed),
+            "DriftDetected" => Some(WebhookEvent::Drifted),
+            _ => None,
+        }
+    }
+}
+
+/// Payload sent to the webhooks
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub namespace: String,
+    pub name: String,
+    pub revision: Option<String>,
+    pub result: WebhookEvent,
+    pub reason: String,
+    pub summary: String,
+    pub timestamp: String,
+}
+
+/// Sends notifications to the configured webhooks. Each webhook has its own bounded queue and
+/// delivery task, so slow receivers never block reconciles or other webhooks.
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    targets: Vec<Target>,
+}
+
+#[derive(Clone)]
+struct Target {
+    url: Url,
+    events: Vec<WebhookEvent>,
+    queue: mpsc::Sender<Notification>,
+}
+
+impl WebhookNotifier {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            Error::Configuration(format!("Could not read webhook config {path:?}: {e}"))
+        })?;
+        let config: WebhooksConfig = serde_yaml::from_str(&content)
+            .map_err(|e| Error::Configuration(format!("Invalid webhook config: {e}")))?;
+        Self::new(config, RETRY_BACKOFF)
+    }
+
+    fn new(config: WebhooksConfig, backoff: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()?;
+        let mut targets = Vec::new();
+        for webhook in config.webhooks {
+            let secret = match webhook.secret_file.as_ref() {
+                Some(file) => Some(std::fs::read(file).map_err(|e| {
+                    Error::Configuration(format!("Could not read webhook secret {file:?}: {e}"))
+                })?),
+                None => None,
+            };
+            let (queue, receiver) = mpsc::channel(QUEUE_SIZE);
+            tokio::spawn(deliver_all(
+                client.clone(),
+                webhook.url.clone(),
+                secret,
+                backoff,
+                receiver,
+            ));
+            targets.push(Target {
+                url: webhook.url,
+                events: webhook.events,
+                queue,
+            });
+        }
+        Ok(Self { targets })
+    }
+
+    /// Queues a notification for an event if it is a reconcile result, never waits for delivery
+    pub fn notify(&self, obj: &Helmfile, event: &Event, revision: Option<&str>) {
+        let Some(result) = WebhookEvent::from_reason(&event.reason) else {
+            return;
+        };
+        let notification = Notification {
+            namespace: obj.namespace().unwrap_or_else(|| NS.to_owned()),
+            name: obj.name_any(),
+            revision: revision.map(str::to_owned),
+            result,
+            reason: event.reason.clone(),
+            summary: event.note.clone().unwrap_or_default(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        };
+        for target in self.targets.iter() {
+            if !target.events.is_empty() && !target.events.contains(&result) {
+                continue;
+            }
+            if let Err(err) = target.queue.try_send(notification.clone()) {
+                tracing::warn!("Dropping notification for webhook {}: {err}", target.url);
+            }
+        }
+    }
+}
+
+async fn deliver_all(
+    client: reqwest::Client,
+    url: Url,
+    secret: Option<Vec<u8>>,
+    backoff: Duration,
+    mut receiver: mpsc::Receiver<Notification>,
+) {
+    while let Some(notification) = receiver.recv().await {
+        let body = match serde_json::to_vec(&notification) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!("Could not serialize notification: {err}");
+                continue;
+            }
+        };
+        if let Err(err) = deliver(&client, &url, secret.as_deref(), &body, backoff).await {
+            tracing::warn!("Could not deliver notification to webhook {url}: {err}");
+        }
+    }
+}
+
+/// Posts the payload, retrying with exponential backoff on connection errors and 5xx or 429 responses
+async fn deliver(
+    client: &reqwest::Client,
+    url: &Url,
+    secret: Option<&[u8]>,
+    body: &[u8],
+    backoff: Duration,
+) -> std::result::Result<(), String> {
+    let mut attempt = 1;
+    loop {
+        let mut request = client
+            .post(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(secret) = secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body));
+        }
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response)
+                if !response.status().is_server_error()
+                    && response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS =>
+            {
+                return Err(format!("webhook responded with {}", response.status()));
+            }
+            Ok(response) => format!("webhook responded with {}", response.status()),
+            Err(err) => err.to_string(),
+        };
+        if attempt >= MAX_ATTEMPTS {
+            return Err(format!("giving up after {attempt} attempts: {error}"));
+        }
+        tracing::debug!("Delivery to webhook {url} failed, retrying: {error}");
+        tokio::time::sleep(backoff * 2u32.pow(attempt - 1)).await;
+        attempt += 1;
+    }
+}
+
+/// Signature of the payload in the format `sha256=<hex encoded HMAC-SHA256>`
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use kube::core::ObjectMeta;
+    use kube::runtime::events::EventType;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sign() {
+        // test vector from RFC 4231, test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        // local stand-in for a webhook receiver that fails the first delivery
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(HeaderMap, Bytes)>();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return StatusCode::SERVICE_UNAVAILABLE;
+                }
+                sender.send((headers, body)).unwrap();
+                StatusCode::OK
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let secret_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(secret_file.path(), "s3cr3t").unwrap();
+        let config = WebhooksConfig {
+            webhooks: vec![WebhookConfig {
+                url: Url::parse(&format!("http://{address}/hook")).unwrap(),
+                events: vec![WebhookEvent::Failed],
+                secret_file: Some(secret_file.path().to_owned()),
+            }],
+        };
+        let notifier = WebhookNotifier::new(config, Duration::from_millis(10)).unwrap();
+        let obj = Helmfile {
+            metadata: ObjectMeta {
+                name: Some("foo".to_owned()),
+                namespace: Some("bar".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let event = |type_, reason: &str, note: &str| Event {
+            type_,
+            reason: reason.to_owned(),
+            note: Some(note.to_owned()),
+            action: "Reconcile".to_owned(),
+            secondary: None,
+        };
+        // filtered out by the webhook config
+        notifier.notify(
+            &obj,
+            &event(
+                EventType::Normal,
+                "Applied",
+                "Applied revision main@sha1:abcd",
+            ),
+            Some("main@sha1:abcd"),
+        );
+        notifier.notify(
+            &obj,
+            &event(EventType::Warning, "ReconciliationFailed", "boom"),
+            Some("main@sha1:abcd"),
+        );
+
+        let (headers, body) = receiver.recv().await.unwrap();
+        assert_eq!(
+            headers[SIGNATURE_HEADER].to_str().unwrap(),
+            sign(b"s3cr3t", &body)
+        );
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(payload["namespace"], "bar");
+        assert_eq!(payload["name"], "foo");
+        assert_eq!(payload["revision"], "main@sha1:abcd");
+        assert_eq!(payload["result"], "failed");
+        assert_eq!(payload["summary"], "boom");
+        assert!(receiver.try_recv().is_err());
+    }
+}